@@ -1,68 +1,228 @@
 use std::{
     collections::HashSet,
-    env,
-    fs::{self, DirEntry},
-    path::PathBuf,
+    env, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
 };
 
-use crate::formater::formater;
-use crate::settings::{SavedSettings, Settings, write_gitignore};
+use crate::error::FormatError;
+use crate::formater::{
+    Formatter, check_file, diff_file, format_script, formater, unified_diff, verify_idempotent,
+};
+use crate::git::{GitChanges, ScriptLines, changed_scripts};
+use crate::resolver::Resolver;
+use crate::settings::{SavedSettings, Settings, write_gitignore, write_template};
+use crate::walk::ScriptFilter;
+use crate::watch::watch;
+use crate::{hook, lsp};
 use mylog::{error, logs};
 use rayon::prelude::*;
 
 const HELP_USAGE: &str = include_str!("../doc/help-usage.txt");
 const HELP_SETTINGS: &str = include_str!("../doc/help-settings.txt");
 
-pub fn main(args: Vec<String>) {
-    let mut settings_path = String::new();
+#[derive(Debug, Default)]
+struct Arguments {
+    settings_path: String,
+    logs_path: String,
+    target_files: HashSet<String>,
+    target_folders: HashSet<String>,
+    /// Target paths which don't exist
+    missing_paths: Vec<String>,
+    /// Arguments starting with `-` which are neither an option nor an existing path
+    unknown_options: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    help_usage: bool,
+    help_settings: bool,
+    status: bool,
+    no_semantic_check: bool,
+    verify_idempotent: bool,
+    check: bool,
+    diff: bool,
+    stdin: bool,
+    stdin_filepath: Option<PathBuf>,
+    lsp: bool,
+    init: bool,
+    install_hook: bool,
+    uninstall_hook: bool,
+    fix: bool,
+    watch: bool,
+    lines: Option<String>,
+    git_changes: Option<GitChanges>,
+    changed_lines: bool,
+}
+
+/// Exit code of a run without errors, where no file would change
+pub const EXIT_SUCCESS: u8 = 0;
+/// Exit code of a run in check mode where some files would change
+pub const EXIT_CHANGED: u8 = 1;
+/// Exit code of a run where some files or the settings couldn't be processed
+pub const EXIT_ERROR: u8 = 2;
+
+/// Outcome of the processing of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success,
+    Changed,
+    Failed,
+}
+
+/// Run the CLI with the arguments in input (without the program name) and return the exit code of
+/// the process
+pub fn main(args: Vec<String>) -> u8 {
     let mut settings: Option<Settings> = None;
-    let mut logs_path = String::new();
-    let mut target_files: HashSet<String> = HashSet::new();
-    let mut target_folders: HashSet<String> = HashSet::new();
-    let mut help_usage = false;
-    let mut help_settings = false;
-    let mut status = false;
-
-    parse_args(
-        args,
-        &mut settings_path,
-        &mut logs_path,
-        &mut target_files,
-        &mut target_folders,
-        &mut help_usage,
-        &mut help_settings,
-        &mut status,
-    );
-
-    if let Err(error) = set_up(&mut settings, &mut settings_path, &mut logs_path) {
+    let mut arguments = parse_args(args);
+    if !arguments.unknown_options.is_empty() {
+        for option in &arguments.unknown_options {
+            eprintln!("ERROR : Unknown option : {} (see --help)", option);
+        }
+        return EXIT_ERROR;
+    }
+    // Without settings path, the settings are discovered from the folder of each script
+    let discover = arguments.settings_path.is_empty();
+
+    if let Err(error) = set_up(
+        &mut settings,
+        &mut arguments.settings_path,
+        &mut arguments.logs_path,
+        !(arguments.init || arguments.help_usage || arguments.help_settings),
+    ) {
         eprintln!("ERROR : {}", error);
-        return;
+        return EXIT_ERROR;
     }
 
-    if help_usage {
+    if arguments.help_usage {
         println!("{}", HELP_USAGE);
-    } else if help_settings {
+    } else if arguments.help_settings {
         println!("{}", HELP_SETTINGS);
-    } else if status {
+    } else if arguments.status {
         println!(
             "\nParsed paths :\nLogs path : {}\nSettings path : {}\n",
-            logs_path, settings_path
+            arguments.logs_path, arguments.settings_path
         );
+    } else if arguments.init {
+        let path = (!discover).then(|| Path::new(&arguments.settings_path));
+        match write_template(path) {
+            Ok(path) => println!("Created the settings file : {}", path.display()),
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        }
+    } else if arguments.install_hook {
+        match Resolver::new(settings.unwrap_or_default(), discover, false)
+            .and_then(|resolver| resolver.settings())
+            .and_then(|settings| hook::install(arguments.fix, &settings.extensions))
+        {
+            Ok(path) => println!("Installed the pre-commit hook : {}", path.display()),
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        }
+    } else if arguments.uninstall_hook {
+        match hook::uninstall() {
+            Ok(path) => println!("Uninstalled the pre-commit hook : {}", path.display()),
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        }
     } else {
-        let settings = settings.unwrap_or_default();
+        if !arguments.missing_paths.is_empty() {
+            for path in &arguments.missing_paths {
+                let error = fs::metadata(path)
+                    .err()
+                    .unwrap_or_else(|| io::ErrorKind::NotFound.into());
+                failed(FormatError::io(Path::new(path), error));
+            }
+            return EXIT_ERROR;
+        }
+
+        let resolver = match Resolver::new(
+            settings.unwrap_or_default(),
+            discover,
+            arguments.no_semantic_check,
+        ) {
+            Ok(resolver) => resolver,
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        };
+
+        let lines = match arguments.lines.as_deref().map(parse_lines) {
+            Some(Some(lines)) => vec![lines],
+            Some(None) => {
+                eprintln!(
+                    "ERROR : Invalid line range : '{}', expected START:END",
+                    arguments.lines.unwrap_or_default()
+                );
+                return EXIT_ERROR;
+            }
+            None => Vec::new(),
+        };
 
+        let filter = match ScriptFilter::new(&arguments.include, &arguments.exclude, &resolver) {
+            Ok(filter) => filter,
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        };
+
+        if arguments.lsp {
+            return match lsp::main(&resolver) {
+                Ok(_) => EXIT_SUCCESS,
+                Err(error) => {
+                    failed(error);
+                    EXIT_ERROR
+                }
+            };
+        }
+
+        if arguments.stdin {
+            return stdin_mode(&resolver, &arguments, &lines);
+        }
+
+        // The extensions of each target are the ones of its own settings
         let mut files_path: Vec<PathBuf> = Vec::new();
-        for folder_path in target_folders {
-            files_path.extend(get_scripts(folder_path));
+        for folder_path in &arguments.target_folders {
+            match filter.scripts(Path::new(folder_path)) {
+                Ok(scripts) => files_path.extend(scripts),
+                Err(error) => {
+                    failed(error);
+                    return EXIT_ERROR;
+                }
+            }
         }
 
-        files_path.extend(target_files.iter().filter_map(|s| {
-            let p = PathBuf::from(&s);
-            if p.exists() { Some(p) } else { None }
-        }));
+        let target_files = match script_files(&resolver, &arguments.target_files) {
+            Ok(files) => files,
+            Err(error) => {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        };
+        files_path.extend(target_files.iter().cloned());
+
+        let mut files: Vec<ScriptLines> = match &arguments.git_changes {
+            Some(changes) => match changed_scripts(changes, arguments.changed_lines, &resolver) {
+                Ok(scripts) => filter_targets(scripts, &arguments, &files_path),
+                Err(error) => {
+                    failed(error);
+                    return EXIT_ERROR;
+                }
+            },
+            None => files_path
+                .into_iter()
+                .map(|path| (path, lines.clone()))
+                .collect(),
+        };
 
-        if files_path.len() < 1 {
-            return;
+        if files.is_empty() && !arguments.watch {
+            return EXIT_SUCCESS;
         }
 
         rayon::ThreadPoolBuilder::new()
@@ -74,71 +234,304 @@ pub fn main(args: Vec<String>) {
             .build_global()
             .unwrap_or_else(|e| error!("{}", e));
 
-        files_path.par_iter().for_each(|path| {
-            let path_string = path.display().to_string();
-            match formater(&settings, path.to_path_buf()) {
-                Ok(_) => {
-                    println!("\nSuccessfully format the file : {}", path_string);
+        files.sort();
+        let outcomes: Vec<Outcome> = files
+            .par_iter()
+            .map(|(path, lines)| {
+                let formatter = match resolver.formatter(path) {
+                    Ok(formatter) => formatter,
+                    Err(error) => return failed(error),
+                };
+                if arguments.diff {
+                    diff_mode(&formatter, path, lines)
+                } else if arguments.check {
+                    check_mode(&formatter, path, lines)
+                } else if arguments.verify_idempotent {
+                    verify_idempotent_mode(&formatter, path)
+                } else {
+                    format_mode(&formatter, path, lines)
                 }
-                Err(error) => {
-                    error!("{}", error);
-                    eprintln!("ERROR : {} with the file : {}", error, path_string);
+            })
+            .collect();
+
+        if arguments.check {
+            let changed = outcomes
+                .iter()
+                .filter(|outcome| **outcome == Outcome::Changed)
+                .count();
+            println!(
+                "\n{} file(s) would be reformatted, {} file(s) already formatted",
+                changed,
+                outcomes
+                    .iter()
+                    .filter(|outcome| **outcome == Outcome::Success)
+                    .count()
+            );
+        }
+
+        if arguments.watch {
+            let folders = arguments
+                .target_folders
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<PathBuf>>();
+            if let Err(error) = watch(&resolver, &filter, &folders, &target_files) {
+                failed(error);
+                return EXIT_ERROR;
+            }
+        }
+
+        return exit_code(&outcomes);
+    }
+
+    EXIT_SUCCESS
+}
+
+/// Target files which exist and have one of the extensions of their settings
+fn script_files(
+    resolver: &Resolver,
+    target_files: &HashSet<String>,
+) -> Result<Vec<PathBuf>, FormatError> {
+    let mut files = Vec::new();
+    for path in target_files.iter().map(PathBuf::from) {
+        if path.exists() && resolver.is_script(&path)? {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Keep the changed scripts inside the target paths, or every changed script without targets
+fn filter_targets(
+    scripts: Vec<ScriptLines>,
+    arguments: &Arguments,
+    files_path: &[PathBuf],
+) -> Vec<ScriptLines> {
+    if arguments.target_folders.is_empty() && arguments.target_files.is_empty() {
+        return scripts;
+    }
+
+    let targets = arguments
+        .target_folders
+        .iter()
+        .map(PathBuf::from)
+        .chain(files_path.iter().cloned())
+        .filter_map(|path| path.canonicalize().ok())
+        .collect::<Vec<PathBuf>>();
+    scripts
+        .into_iter()
+        .filter(|(path, _)| {
+            path.canonicalize()
+                .is_ok_and(|path| targets.iter().any(|target| path.starts_with(target)))
+        })
+        .collect()
+}
+
+/// Format the script read from stdin and write it to stdout (or its diff with `--diff`, nothing
+/// with `--check`). The settings are the ones of the script at `--stdin-filepath`
+fn stdin_mode(resolver: &Resolver, arguments: &Arguments, lines: &[(usize, usize)]) -> u8 {
+    let name = arguments
+        .stdin_filepath
+        .as_ref()
+        .map_or("<stdin>".to_string(), |path| path.display().to_string());
+    let formatter = match &arguments.stdin_filepath {
+        Some(path) => resolver.formatter(path),
+        None => resolver.default_formatter(),
+    };
+
+    let mut script = String::new();
+    let formatted = formatter.and_then(|formatter| {
+        io::stdin()
+            .read_to_string(&mut script)
+            .map_err(|e| FormatError::io(Path::new(&name), e))?;
+        format_script(&formatter, &script, lines).map_err(|e| e.with_path(Path::new(&name)))
+    });
+
+    let outcome = match formatted {
+        Ok(formatted) if arguments.diff || arguments.check => {
+            if formatted == script {
+                Outcome::Success
+            } else {
+                if arguments.diff {
+                    print!("{}", unified_diff(&name, &script, &formatted));
+                } else {
+                    println!("Would reformat : {}", name);
                 }
+                Outcome::Changed
             }
-        });
+        }
+        Ok(formatted) => {
+            print!("{}", formatted);
+            Outcome::Success
+        }
+        Err(error) => failed(error),
+    };
+    exit_code(&[outcome])
+}
+
+fn format_mode(formatter: &Formatter, path: &Path, lines: &[(usize, usize)]) -> Outcome {
+    match formater(formatter, path.to_path_buf(), lines) {
+        Ok(_) => {
+            println!("\nSuccessfully format the file : {}", path.display());
+            Outcome::Success
+        }
+        Err(error) => failed(error),
     }
 }
 
-fn parse_args(
-    args: Vec<String>,
-    settings_path: &mut String,
-    logs_path: &mut String,
-    target_files: &mut HashSet<String>,
-    target_folders: &mut HashSet<String>,
-    help_usage: &mut bool,
-    help_settings: &mut bool,
-    status: &mut bool,
-) {
+fn check_mode(formatter: &Formatter, path: &Path, lines: &[(usize, usize)]) -> Outcome {
+    match check_file(formatter, path.to_path_buf(), lines) {
+        Ok(true) => Outcome::Success,
+        Ok(false) => {
+            println!("Would reformat : {}", path.display());
+            Outcome::Changed
+        }
+        Err(error) => failed(error),
+    }
+}
+
+fn diff_mode(formatter: &Formatter, path: &Path, lines: &[(usize, usize)]) -> Outcome {
+    match diff_file(formatter, path.to_path_buf(), lines) {
+        Ok(None) => Outcome::Success,
+        Ok(Some(diff)) => {
+            print!("{}", diff);
+            Outcome::Changed
+        }
+        Err(error) => failed(error),
+    }
+}
+
+fn verify_idempotent_mode(formatter: &Formatter, path: &Path) -> Outcome {
+    match verify_idempotent(formatter, path.to_path_buf()) {
+        Ok(_) => {
+            println!("\nThe formatting is idempotent : {}", path.display());
+            Outcome::Success
+        }
+        Err(error @ FormatError::NotIdempotent { .. }) => {
+            failed(error);
+            Outcome::Changed
+        }
+        Err(error) => failed(error),
+    }
+}
+
+fn failed(error: FormatError) -> Outcome {
+    error!("{}", error);
+    eprintln!("ERROR : {}", error);
+    Outcome::Failed
+}
+
+fn exit_code(outcomes: &[Outcome]) -> u8 {
+    if outcomes.contains(&Outcome::Failed) {
+        EXIT_ERROR
+    } else if outcomes.contains(&Outcome::Changed) {
+        EXIT_CHANGED
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Arguments {
+    let mut arguments = Arguments::default();
+
     for arg in args {
-        if ["-help", "--help"].contains(&arg.as_str()) {
-            *help_usage = true;
+        if arg == "lsp" {
+            arguments.lsp = true;
+        } else if arg == "init" {
+            arguments.init = true;
+        } else if arg == "install-hook" {
+            arguments.install_hook = true;
+        } else if arg == "uninstall-hook" {
+            arguments.uninstall_hook = true;
+        } else if ["-fix", "--fix"].contains(&arg.as_str()) {
+            arguments.fix = true;
+        } else if ["-help", "--help"].contains(&arg.as_str()) {
+            arguments.help_usage = true;
         } else if ["-help-settings", "--help-settings"].contains(&arg.as_str()) {
-            *help_settings = true;
+            arguments.help_settings = true;
         } else if ["-status", "--status"].contains(&arg.as_str()) {
-            *status = true;
+            arguments.status = true;
+        } else if ["-no-semantic-check", "--no-semantic-check"].contains(&arg.as_str()) {
+            arguments.no_semantic_check = true;
+        } else if ["-verify-idempotent", "--verify-idempotent"].contains(&arg.as_str()) {
+            arguments.verify_idempotent = true;
+        } else if ["-watch", "--watch"].contains(&arg.as_str()) {
+            arguments.watch = true;
+        } else if ["-check", "--check"].contains(&arg.as_str()) {
+            arguments.check = true;
+        } else if ["-diff", "--diff"].contains(&arg.as_str()) {
+            arguments.diff = true;
+        } else if ["-", "-stdin", "--stdin"].contains(&arg.as_str()) {
+            arguments.stdin = true;
+        } else if arg.starts_with("-stdin-filepath=") || arg.starts_with("--stdin-filepath=") {
+            if let Some(path) = arg.split("=").collect::<Vec<&str>>().get(1) {
+                arguments.stdin_filepath = Some(PathBuf::from(path));
+            }
         } else if [".", "*"].contains(&arg.as_str()) {
             if let Ok(path) = env::current_dir() {
-                target_folders.insert(path.display().to_string());
+                arguments.target_folders.insert(path.display().to_string());
+            }
+        } else if ["-staged", "--staged"].contains(&arg.as_str()) {
+            arguments.git_changes = Some(GitChanges::Staged);
+        } else if ["-changed-lines", "--changed-lines"].contains(&arg.as_str()) {
+            arguments.changed_lines = true;
+        } else if arg.starts_with("-changed-since=") || arg.starts_with("--changed-since=") {
+            if let Some(revision) = arg.split("=").collect::<Vec<&str>>().get(1) {
+                arguments.git_changes = Some(GitChanges::Since(revision.to_string()));
+            }
+        } else if arg.starts_with("-include=") || arg.starts_with("--include=") {
+            if let Some(glob) = arg.split("=").collect::<Vec<&str>>().get(1) {
+                arguments.include.push(glob.to_string());
+            }
+        } else if arg.starts_with("-exclude=") || arg.starts_with("--exclude=") {
+            if let Some(glob) = arg.split("=").collect::<Vec<&str>>().get(1) {
+                arguments.exclude.push(glob.to_string());
+            }
+        } else if arg.starts_with("-lines=") || arg.starts_with("--lines=") {
+            if let Some(lines) = arg.split("=").collect::<Vec<&str>>().get(1) {
+                arguments.lines = Some(lines.to_string());
             }
         } else if arg.starts_with("-logs_path=") || arg.starts_with("--logs_path=") {
             if let Some(path) = arg.split("=").collect::<Vec<&str>>().get(1) {
-                logs_path.push_str(path);
+                arguments.logs_path.push_str(path);
             }
         } else if arg.starts_with("-settings_path=") || arg.starts_with("--settings_path=") {
             if let Some(path) = arg.split("=").collect::<Vec<&str>>().get(1) {
-                *settings_path = path.to_string();
+                arguments.settings_path = path.to_string();
             }
         } else {
-            let path = PathBuf::from(arg);
+            let path = PathBuf::from(&arg);
             if fs::exists(&path).unwrap_or(false) {
                 if path.is_dir() {
-                    target_folders.insert(path.display().to_string());
+                    arguments.target_folders.insert(path.display().to_string());
                 } else {
-                    let path = path.display().to_string();
-                    if path.ends_with(".sql") {
-                        target_files.insert(path);
-                    }
+                    // The extensions are checked with the settings
+                    arguments.target_files.insert(path.display().to_string());
                 }
+            } else if arg.starts_with('-') {
+                arguments.unknown_options.push(arg);
+            } else {
+                arguments.missing_paths.push(arg);
             }
         }
     }
+
+    arguments
+}
+
+/// Parse the line range `START:END` (starting from 1, both included)
+fn parse_lines(lines: &str) -> Option<(usize, usize)> {
+    let (start, end) = lines.split_once(':')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    (0 < start && start <= end).then_some((start, end))
 }
 
 fn set_up(
     settings: &mut Option<Settings>,
     settings_path: &mut String,
     logs_path: &mut str,
-) -> Result<(), String> {
+    load_settings: bool,
+) -> Result<(), FormatError> {
     let mut folder_path = env::current_dir().unwrap_or_default();
     folder_path.push("sqlformater");
 
@@ -147,61 +540,33 @@ fn set_up(
             folder_path.display().to_string(),
             "1MB".to_string(),
             "7days".to_string(),
-        )?;
+        )
+        .map_err(|e| FormatError::io(&folder_path, io::Error::other(e)))?;
         write_gitignore(&folder_path)?;
     } else {
         logs::init(
             logs_path.to_string(),
             "1MB".to_string(),
             "7days".to_string(),
-        )?;
+        )
+        .map_err(|e| FormatError::io(Path::new(logs_path), io::Error::other(e)))?;
         let path = PathBuf::from(logs_path.to_string());
         write_gitignore(&path)?;
     }
 
+    if !load_settings {
+        return Ok(());
+    }
+
     if settings_path.is_empty() {
-        let saved_settings = SavedSettings::main(None);
+        let saved_settings = SavedSettings::main(None)?;
         *settings_path = saved_settings.1;
         *settings = Some(saved_settings.0);
     } else {
-        let saved_settings = SavedSettings::main(Some(PathBuf::from(settings_path.to_owned())));
+        let saved_settings = SavedSettings::main(Some(PathBuf::from(settings_path.to_owned())))?;
         *settings_path = saved_settings.1;
         *settings = Some(saved_settings.0);
     }
 
-    let path = PathBuf::from(settings_path.clone());
-    if let Some(folder_parent) = path.parent() {
-        write_gitignore(&folder_parent.to_path_buf())?;
-    }
-
     Ok(())
 }
-
-fn get_scripts(folder_path: String) -> Vec<PathBuf> {
-    let folder_path = PathBuf::from(folder_path);
-    let mut scripts_path: Vec<PathBuf> = Vec::new();
-
-    match fs::read_dir(folder_path) {
-        Ok(entries) => {
-            let entries = entries.flatten().collect::<Vec<DirEntry>>();
-
-            for entry in entries {
-                let path = entry.path();
-                if path.is_dir() {
-                    scripts_path.extend(get_scripts(path.display().to_string()));
-                } else {
-                    let file_name = path.display().to_string();
-                    if file_name.ends_with(".sql") {
-                        scripts_path.push(path);
-                    }
-                }
-            }
-
-            scripts_path
-        }
-        Err(error) => {
-            error!("{}", error);
-            scripts_path
-        }
-    }
-}
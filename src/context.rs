@@ -0,0 +1,446 @@
+use sqlparser::keywords::Keyword;
+use std::collections::HashSet;
+
+use sqlparser::tokenizer::{Token, Whitespace, Word};
+
+use crate::settings::{ParenthesisLayout, Settings};
+
+/// Kind of construct enclosed by a pair of parenthesis, or of brackets and braces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ParenthesisKind {
+    /// `(SELECT ...)`, `(WITH ...)`
+    Subquery,
+    /// `COUNT(*)`, `COALESCE(a, b)`
+    FunctionCall,
+    /// `VARCHAR(20)`, `DECIMAL(10, 2)`
+    TypeModifier,
+    /// `IN (1, 2, 3)`, and the rows of a `VALUES` clause `VALUES (1, 2), (3, 4)`
+    InList,
+    /// `CREATE TABLE t (...)`
+    ColumnDefinitions,
+    /// `INSERT INTO t (...)`
+    InsertColumns,
+    /// Any other parenthesis, i.e. `(a + b) * c`
+    Grouping,
+    /// `ARRAY[1, 2]`, `a[1]`
+    Brackets,
+    /// `{'a': 1, 'b': 2}`
+    Braces,
+}
+
+/// A parenthesis opened and not closed yet
+#[derive(Debug, Clone)]
+pub(crate) struct Parenthesis {
+    pub kind: ParenthesisKind,
+    pub layout: ParenthesisLayout,
+    /// Indentation level before the parenthesis
+    pub level: usize,
+    /// Construct enclosing the parenthesis
+    pub construct: Construct,
+    /// Indentation level of the clauses enclosing the parenthesis
+    pub clause_level: Option<usize>,
+}
+
+/// Keywords matched as a unit, i.e. `GROUP BY`, `LEFT OUTER JOIN`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Phrase {
+    /// Words of the phrase as written in the script
+    pub words: Vec<String>,
+    /// Index of the last word of the phrase
+    pub end: usize,
+}
+
+impl Phrase {
+    /// Match the longest phrase starting at the index in input, the words of the phrase could be
+    /// separated by any whitespaces. The phrases must be normalized with `normalize_phrase`
+    pub fn match_longest(
+        tokens: &[Token],
+        index: usize,
+        phrases: &HashSet<String>,
+    ) -> Option<Phrase> {
+        let max_words = phrases
+            .iter()
+            .map(|phrase| phrase.split(' ').count())
+            .max()?;
+
+        let mut words: Vec<(String, usize)> = Vec::new();
+        let mut current = index;
+        while words.len() < max_words {
+            match tokens.get(current) {
+                Some(Token::Word(word)) if word.quote_style.is_none() => {
+                    words.push((word.value.clone(), current));
+                }
+                _ => break,
+            }
+            current += 1;
+            while let Some(Token::Whitespace(
+                Whitespace::Space | Whitespace::Tab | Whitespace::Newline,
+            )) = tokens.get(current)
+            {
+                current += 1;
+            }
+        }
+
+        (1..=words.len()).rev().find_map(|length| {
+            let candidate = normalize_phrase(
+                &words[..length]
+                    .iter()
+                    .map(|(word, _)| word.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            );
+            phrases.contains(&candidate).then(|| Phrase {
+                words: words[..length]
+                    .iter()
+                    .map(|(word, _)| word.clone())
+                    .collect(),
+                end: words[length - 1].1,
+            })
+        })
+    }
+}
+
+/// Separate the words of the phrase by a single space
+pub(crate) fn normalize_whitespace(phrase: &str) -> String {
+    phrase.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Uppercase the phrase and separate its words by a single space
+pub(crate) fn normalize_phrase(phrase: &str) -> String {
+    normalize_whitespace(phrase).to_uppercase()
+}
+
+/// Directive written in a single line comment, i.e. `-- sqlformater: off`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Directive {
+    /// Copy the script verbatim until the directive `on`
+    Off,
+    /// Format the script again after the directive `off`
+    On,
+    /// Copy the next statement verbatim
+    SkipNextStatement,
+}
+
+impl Directive {
+    /// Parse the text of a single line comment without its prefix
+    pub fn parse(comment: &str) -> Option<Directive> {
+        let directive = comment.trim().strip_prefix("sqlformater:")?;
+        match directive.trim().to_lowercase().as_str() {
+            "off" => Some(Directive::Off),
+            "on" => Some(Directive::On),
+            "skip-next-statement" => Some(Directive::SkipNextStatement),
+            _ => None,
+        }
+    }
+
+    /// Directive of the token in input, if the token is a single line comment holding one
+    pub fn from_token(token: &Token) -> Option<Directive> {
+        match token {
+            Token::Whitespace(Whitespace::SingleLineComment { comment, .. }) => {
+                Directive::parse(comment)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Construct enclosing a comma
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Construct {
+    Select,
+    From,
+    GroupBy,
+    OrderBy,
+    Values,
+    Set,
+    ColumnDefinitions,
+    InsertColumns,
+    FunctionCall,
+    TypeModifier,
+    InList,
+    Other,
+}
+
+impl Construct {
+    /// Name of the construct in the setting `linebreak_after_comma_in`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Construct::Select => "select",
+            Construct::From => "from",
+            Construct::GroupBy => "group_by",
+            Construct::OrderBy => "order_by",
+            Construct::Values => "values",
+            Construct::Set => "set",
+            Construct::ColumnDefinitions => "column_definitions",
+            Construct::InsertColumns => "insert_columns",
+            Construct::FunctionCall => "function_call",
+            Construct::TypeModifier => "type_modifier",
+            Construct::InList => "in_list",
+            Construct::Other => "other",
+        }
+    }
+
+    /// Construct started by the keyword in input, if the keyword starts a clause
+    pub fn from_keyword(keyword: Keyword) -> Option<Construct> {
+        match keyword {
+            Keyword::SELECT => Some(Construct::Select),
+            Keyword::FROM => Some(Construct::From),
+            Keyword::GROUP => Some(Construct::GroupBy),
+            Keyword::ORDER => Some(Construct::OrderBy),
+            Keyword::VALUES => Some(Construct::Values),
+            Keyword::SET => Some(Construct::Set),
+            Keyword::WHERE
+            | Keyword::HAVING
+            | Keyword::JOIN
+            | Keyword::ON
+            | Keyword::USING
+            | Keyword::LIMIT
+            | Keyword::OFFSET
+            | Keyword::WINDOW
+            | Keyword::QUALIFY
+            | Keyword::RETURNING
+            | Keyword::UNION
+            | Keyword::INTERSECT
+            | Keyword::EXCEPT => Some(Construct::Other),
+            _ => None,
+        }
+    }
+}
+
+impl ParenthesisKind {
+    /// Classify the parenthesis at the index in input from the tokens around it and the construct
+    /// enclosing it
+    pub fn classify(tokens: &[Token], index: usize, construct: Construct) -> ParenthesisKind {
+        if let Some(Token::Word(word)) = next_significant(tokens, index)
+            && matches!(word.keyword, Keyword::SELECT | Keyword::WITH)
+        {
+            return ParenthesisKind::Subquery;
+        }
+
+        let Some(previous) = previous_significant_index(tokens, index) else {
+            return ParenthesisKind::Grouping;
+        };
+
+        // The rows of a VALUES clause are lists of values, i.e. `VALUES (1, 2), (3, 4)`
+        if construct == Construct::Values
+            && matches!(
+                &tokens[previous],
+                Token::Comma
+                    | Token::Word(Word {
+                        keyword: Keyword::VALUES,
+                        ..
+                    })
+            )
+        {
+            return ParenthesisKind::InList;
+        }
+
+        let Token::Word(word) = &tokens[previous] else {
+            return ParenthesisKind::Grouping;
+        };
+
+        match table_name_keyword(tokens, previous) {
+            Some(Keyword::TABLE) => return ParenthesisKind::ColumnDefinitions,
+            Some(_) => return ParenthesisKind::InsertColumns,
+            None => {}
+        }
+
+        match word.keyword {
+            _ if word.quote_style.is_some() => ParenthesisKind::FunctionCall,
+            Keyword::IN => ParenthesisKind::InList,
+            keyword if is_type_keyword(keyword) => ParenthesisKind::TypeModifier,
+            keyword if is_expression_keyword(keyword) => ParenthesisKind::Grouping,
+            _ => ParenthesisKind::FunctionCall,
+        }
+    }
+
+    /// Construct of the commas between this kind of parenthesis, `None` if the commas belong to
+    /// the clause inside the parenthesis
+    pub fn construct(&self) -> Option<Construct> {
+        match self {
+            ParenthesisKind::Subquery => None,
+            ParenthesisKind::FunctionCall => Some(Construct::FunctionCall),
+            ParenthesisKind::TypeModifier => Some(Construct::TypeModifier),
+            ParenthesisKind::InList => Some(Construct::InList),
+            ParenthesisKind::ColumnDefinitions => Some(Construct::ColumnDefinitions),
+            ParenthesisKind::InsertColumns => Some(Construct::InsertColumns),
+            ParenthesisKind::Grouping | ParenthesisKind::Brackets | ParenthesisKind::Braces => {
+                Some(Construct::Other)
+            }
+        }
+    }
+
+    /// Layout of this kind of parenthesis from the settings
+    pub fn layout(&self, settings: &Settings) -> ParenthesisLayout {
+        match self {
+            ParenthesisKind::Subquery => settings.parenthesis_subquery,
+            ParenthesisKind::FunctionCall => settings.parenthesis_function_call,
+            ParenthesisKind::TypeModifier => settings.parenthesis_type_modifier,
+            ParenthesisKind::InList => settings.parenthesis_in_list,
+            ParenthesisKind::ColumnDefinitions | ParenthesisKind::InsertColumns => {
+                settings.parenthesis_column_definitions
+            }
+            ParenthesisKind::Grouping => ParenthesisLayout {
+                linebreak: settings.linebreak_after_lparenthesis,
+                indentation: settings.indentation_parenthesis,
+            },
+            ParenthesisKind::Brackets => ParenthesisLayout {
+                linebreak: settings.linebreak_after_lbracket,
+                indentation: settings.indentation_brackets,
+            },
+            ParenthesisKind::Braces => ParenthesisLayout {
+                linebreak: settings.linebreak_after_lbrace,
+                indentation: settings.indentation_braces,
+            },
+        }
+    }
+}
+
+pub(crate) fn is_significant(token: &Token) -> bool {
+    !matches!(token, Token::Whitespace(_) | Token::EOF)
+}
+
+pub(crate) fn next_significant(tokens: &[Token], index: usize) -> Option<&Token> {
+    tokens
+        .iter()
+        .skip(index + 1)
+        .find(|token| is_significant(token))
+}
+
+pub(crate) fn next_significant_index(tokens: &[Token], index: usize) -> Option<usize> {
+    (index + 1..tokens.len()).find(|i| is_significant(&tokens[*i]))
+}
+
+/// Index of the last space, tab or newline of the run of whitespaces starting at the index in input
+pub(crate) fn whitespace_run_end(tokens: &[Token], index: usize) -> usize {
+    let mut end = index;
+    while let Some(Token::Whitespace(Whitespace::Space | Whitespace::Tab | Whitespace::Newline)) =
+        tokens.get(end + 1)
+    {
+        end += 1;
+    }
+    end
+}
+
+/// Return true if the comment at the index in input follows some code on the same line
+pub(crate) fn is_trailing_comment(tokens: &[Token], index: usize) -> bool {
+    match tokens[..index].iter().rev().find(|token| {
+        !matches!(
+            token,
+            Token::Whitespace(Whitespace::Space | Whitespace::Tab)
+        )
+    }) {
+        None
+        | Some(Token::Whitespace(Whitespace::Newline | Whitespace::SingleLineComment { .. })) => {
+            false
+        }
+        Some(_) => true,
+    }
+}
+
+pub(crate) fn previous_significant_index(tokens: &[Token], index: usize) -> Option<usize> {
+    (0..index).rev().find(|i| is_significant(&tokens[*i]))
+}
+
+/// Return `TABLE` or `INTO` if the word at the index in input is the name of the table of a
+/// `CREATE TABLE` or an `INSERT INTO` statement (i.e. `TABLE [IF NOT EXISTS] schema.name`)
+fn table_name_keyword(tokens: &[Token], index: usize) -> Option<Keyword> {
+    let mut index = index;
+    loop {
+        match previous_significant_index(tokens, index).map(|i| (i, &tokens[i])) {
+            Some((period, Token::Period)) => {
+                match previous_significant_index(tokens, period).map(|i| (i, &tokens[i])) {
+                    Some((name, Token::Word(_))) => index = name,
+                    _ => return None,
+                }
+            }
+            Some((keyword, Token::Word(word))) if word.quote_style.is_none() => {
+                match word.keyword {
+                    Keyword::TABLE | Keyword::INTO => return Some(word.keyword),
+                    Keyword::EXISTS | Keyword::NOT | Keyword::IF => index = keyword,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn is_type_keyword(keyword: Keyword) -> bool {
+    matches!(
+        keyword,
+        Keyword::CHAR
+            | Keyword::CHARACTER
+            | Keyword::VARCHAR
+            | Keyword::NVARCHAR
+            | Keyword::NCHAR
+            | Keyword::BINARY
+            | Keyword::VARBINARY
+            | Keyword::DECIMAL
+            | Keyword::DEC
+            | Keyword::NUMERIC
+            | Keyword::FLOAT
+            | Keyword::DOUBLE
+            | Keyword::REAL
+            | Keyword::BIT
+            | Keyword::TIME
+            | Keyword::TIMESTAMP
+            | Keyword::DATETIME
+            | Keyword::DATETIME64
+            | Keyword::INTERVAL
+            | Keyword::INT
+            | Keyword::INTEGER
+            | Keyword::BIGINT
+            | Keyword::SMALLINT
+            | Keyword::TINYINT
+            | Keyword::MEDIUMINT
+            | Keyword::BLOB
+            | Keyword::TEXT
+            | Keyword::STRING
+            | Keyword::BYTES
+            | Keyword::VARYING
+    )
+}
+
+/// Keywords followed by an expression rather than by the arguments of a function
+fn is_expression_keyword(keyword: Keyword) -> bool {
+    matches!(
+        keyword,
+        Keyword::AND
+            | Keyword::OR
+            | Keyword::NOT
+            | Keyword::XOR
+            | Keyword::ON
+            | Keyword::WHERE
+            | Keyword::FROM
+            | Keyword::JOIN
+            | Keyword::SELECT
+            | Keyword::WHEN
+            | Keyword::THEN
+            | Keyword::ELSE
+            | Keyword::AS
+            | Keyword::VALUES
+            | Keyword::BY
+            | Keyword::HAVING
+            | Keyword::BETWEEN
+            | Keyword::IS
+            | Keyword::LIKE
+            | Keyword::ILIKE
+            | Keyword::USING
+            | Keyword::RETURNING
+            | Keyword::SET
+            | Keyword::CASE
+            | Keyword::EXISTS
+            | Keyword::ANY
+            | Keyword::ALL
+            | Keyword::SOME
+            | Keyword::UNION
+            | Keyword::INTERSECT
+            | Keyword::EXCEPT
+            | Keyword::DISTINCT
+            | Keyword::QUALIFY
+            | Keyword::RETURN
+            | Keyword::LIMIT
+            | Keyword::OFFSET
+    )
+}
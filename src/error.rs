@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use sqlparser::tokenizer::TokenizerError;
+
+/// Position in a source file (line and column start from 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: u64,
+    pub column: u64,
+}
+
+/// Errors raised while loading the settings or formatting a SQL script.
+#[derive(Debug, Clone)]
+pub enum FormatError {
+    /// Failed to read or write a file
+    Io {
+        path: Option<PathBuf>,
+        source: Arc<io::Error>,
+    },
+    /// The script couldn't be tokenized with the configured dialect
+    Tokenizer {
+        path: Option<PathBuf>,
+        span: Span,
+        message: String,
+    },
+    /// The settings file couldn't be parsed or holds invalid values
+    InvalidSettings {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        message: String,
+    },
+    /// The setting `keywords_case` holds an unsupported value
+    UnsupportedCase { path: Option<PathBuf>, case: String },
+    /// The formatted script doesn't hold the same tokens as the original script
+    SemanticChange {
+        path: Option<PathBuf>,
+        span: Span,
+        message: String,
+    },
+    /// The formatted script changes when it's formatted again (the span is in the formatted script)
+    NotIdempotent {
+        path: Option<PathBuf>,
+        span: Span,
+        message: String,
+    },
+}
+
+impl FormatError {
+    pub fn io(path: &Path, source: io::Error) -> FormatError {
+        FormatError::Io {
+            path: Some(path.to_path_buf()),
+            source: Arc::new(source),
+        }
+    }
+
+    /// Attach the path of the file involved, if the error doesn't already have one
+    pub fn with_path(mut self, new_path: &Path) -> FormatError {
+        match &mut self {
+            FormatError::Io { path, .. }
+            | FormatError::Tokenizer { path, .. }
+            | FormatError::InvalidSettings { path, .. }
+            | FormatError::UnsupportedCase { path, .. }
+            | FormatError::SemanticChange { path, .. }
+            | FormatError::NotIdempotent { path, .. } => {
+                if path.is_none() {
+                    *path = Some(new_path.to_path_buf());
+                }
+            }
+        }
+        self
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FormatError::Io { path, .. }
+            | FormatError::Tokenizer { path, .. }
+            | FormatError::InvalidSettings { path, .. }
+            | FormatError::UnsupportedCase { path, .. }
+            | FormatError::SemanticChange { path, .. }
+            | FormatError::NotIdempotent { path, .. } => path.as_deref(),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            FormatError::Tokenizer { span, .. }
+            | FormatError::SemanticChange { span, .. }
+            | FormatError::NotIdempotent { span, .. } => Some(*span),
+            FormatError::InvalidSettings { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// Message of the error without the path and the position
+    pub fn message(&self) -> String {
+        match self {
+            FormatError::Io { source, .. } => source.to_string(),
+            FormatError::Tokenizer { message, .. }
+            | FormatError::InvalidSettings { message, .. }
+            | FormatError::SemanticChange { message, .. }
+            | FormatError::NotIdempotent { message, .. } => message.clone(),
+            FormatError::UnsupportedCase { case, .. } => format!("Unsupported case : '{}'", case),
+        }
+    }
+}
+
+impl From<TokenizerError> for FormatError {
+    fn from(error: TokenizerError) -> Self {
+        FormatError::Tokenizer {
+            path: None,
+            span: Span {
+                line: error.location.line,
+                column: error.location.column,
+            },
+            message: error.message,
+        }
+    }
+}
+
+impl From<serde_json::Error> for FormatError {
+    fn from(error: serde_json::Error) -> Self {
+        let span = match error.line() {
+            0 => None,
+            line => Some(Span {
+                line: line as u64,
+                column: error.column() as u64,
+            }),
+        };
+        let mut message = error.to_string();
+        if let Some(position) = message.rfind(" at line ") {
+            message.truncate(position);
+        }
+
+        FormatError::InvalidSettings {
+            path: None,
+            span,
+            message,
+        }
+    }
+}
+
+impl From<serde_yaml::Error> for FormatError {
+    fn from(error: serde_yaml::Error) -> Self {
+        let span = error.location().map(|location| Span {
+            line: location.line() as u64,
+            column: location.column() as u64,
+        });
+        let mut message = error.to_string();
+        if let Some(position) = message.find(" at line ") {
+            message.truncate(position);
+        }
+
+        FormatError::InvalidSettings {
+            path: None,
+            span,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.path(), self.span()) {
+            (Some(path), Some(span)) => {
+                write!(f, "{}:{}:{}: ", path.display(), span.line, span.column)?
+            }
+            (Some(path), None) => write!(f, "{}: ", path.display())?,
+            (None, Some(span)) => write!(f, "{}:{}: ", span.line, span.column)?,
+            (None, None) => {}
+        }
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for FormatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FormatError::Io { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
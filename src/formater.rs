@@ -1,9 +1,22 @@
+use std::collections::HashSet;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::settings::Settings;
-use sqlparser::dialect::{GenericDialect, dialect_from_str};
+use crate::context::{
+    Construct, Directive, Parenthesis, ParenthesisKind, Phrase, is_trailing_comment,
+    next_significant_index, normalize_phrase, whitespace_run_end,
+};
+use crate::error::{FormatError, Span};
+use crate::range::{line_starts, location_offset};
+use crate::semantic::check_tokens;
+use crate::settings::{Settings, check_database, parse_tabulation_format};
+use similar::TextDiff;
+use sqlparser::dialect::{
+    AnsiDialect, BigQueryDialect, ClickHouseDialect, DatabricksDialect, Dialect, DuckDbDialect,
+    GenericDialect, HiveDialect, MsSqlDialect, MySqlDialect, PostgreSqlDialect, RedshiftSqlDialect,
+    SQLiteDialect, SnowflakeDialect,
+};
 use sqlparser::keywords::Keyword;
 use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
 
@@ -23,11 +36,14 @@ impl IndentationCount {
         self.0 = self.0.saturating_sub(1);
     }
 
-    #[allow(unused)]
     pub fn get(&self) -> usize {
         self.0
     }
 
+    pub fn set(&mut self, value: usize) {
+        self.0 = value;
+    }
+
     pub fn get_string(&self, skip: Option<usize>) -> String {
         if let Some(number) = skip {
             self.1.repeat(self.0.saturating_sub(number))
@@ -37,222 +53,699 @@ impl IndentationCount {
     }
 }
 
-pub fn formater(settings: &Settings, script_path: PathBuf) -> Result<(), String> {
+/// A reusable formatter holding the resolved dialect and settings.
+#[derive(Debug)]
+pub struct Formatter {
+    settings: Settings,
+    dialect: Box<dyn Dialect + Send + Sync>,
+    tabulation: String,
+    /// Normalized phrases of the setting `clauses`
+    clauses: HashSet<String>,
+    /// Normalized phrases of the settings `linebreak_before_keywords` and
+    /// `linebreak_after_keywords` (with the clauses if `indentation_clauses` is true), matched
+    /// case-insensitively
+    linebreak_before: HashSet<String>,
+    linebreak_after: HashSet<String>,
+    /// Normalized multi-word phrases of both settings, matched as units in the scripts
+    keyword_phrases: HashSet<String>,
+}
+
+impl Formatter {
+    pub fn new(settings: Settings) -> Result<Formatter, FormatError> {
+        match settings.keywords_case.as_str() {
+            "lowercase" | "lower" | "uppercase" | "upper" => {}
+            unsupported_case => {
+                return Err(FormatError::UnsupportedCase {
+                    path: None,
+                    case: unsupported_case.to_string(),
+                });
+            }
+        }
+
+        if !["tokens", "ast"].contains(&settings.engine.as_str()) {
+            return Err(FormatError::InvalidSettings {
+                path: None,
+                span: None,
+                message: format!("Unsupported engine : '{}'", settings.engine),
+            });
+        }
+
+        let invalid = |message: String| FormatError::InvalidSettings {
+            path: None,
+            span: None,
+            message,
+        };
+        check_database(&settings.database).map_err(invalid)?;
+        let tabulation = parse_tabulation_format(&settings.tabulation_format).map_err(invalid)?;
+
+        // The clauses are laid out with a line break, the settings in input are left untouched
+        let mut keywords = settings.clone();
+        keywords.insert_clause_keywords();
+
+        Ok(Formatter {
+            dialect: dialect_from_name(&settings.database),
+            tabulation,
+            clauses: settings
+                .clauses
+                .iter()
+                .map(|c| normalize_phrase(c))
+                .collect(),
+            linebreak_before: settings
+                .linebreak_before_keywords
+                .iter()
+                .map(|k| normalize_phrase(k))
+                .collect(),
+            linebreak_after: keywords
+                .linebreak_after_keywords
+                .iter()
+                .map(|k| normalize_phrase(k))
+                .collect(),
+            keyword_phrases: settings
+                .linebreak_before_keywords
+                .iter()
+                .chain(keywords.linebreak_after_keywords.iter())
+                .filter(|k| k.split_whitespace().count() > 1)
+                .map(|k| normalize_phrase(k))
+                .collect(),
+            settings,
+        })
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn dialect(&self) -> &dyn Dialect {
+        self.dialect.as_ref()
+    }
+
+    /// Indentation string resolved from the setting `tabulation_format`
+    pub(crate) fn tabulation(&self) -> &str {
+        &self.tabulation
+    }
+
+    /// Match the longest clause of the setting `clauses` starting at the index in input
+    fn match_clause(&self, tokens: &[Token], index: usize) -> Option<Phrase> {
+        if !self.settings.indentation_clauses {
+            return None;
+        }
+        Phrase::match_longest(tokens, index, &self.clauses)
+    }
+
+    /// Return true if the keyword in input is one of the setting `clauses` laid out by
+    /// `indentation_clauses`
+    fn is_clause(&self, keyword: &str) -> bool {
+        self.settings.indentation_clauses && self.clauses.contains(&normalize_phrase(keyword))
+    }
+
+    /// Apply the setting `keywords_case` to the keyword in input
+    pub(crate) fn keyword_case(&self, keyword: &str) -> String {
+        match self.settings.keywords_case.as_str() {
+            "lowercase" | "lower" => keyword.to_lowercase(),
+            _ => keyword.to_uppercase(),
+        }
+    }
+
+    /// Format the SQL script in input and return the formatted script
+    pub fn format(&self, script: &str) -> Result<String, FormatError> {
+        let (tokens, texts) = tokenize(self.dialect(), script)?;
+
+        let formatted = match self.settings.engine.as_str() {
+            "ast" => self.ast_format(tokens, &texts)?,
+            _ => self.process_format(tokens, &texts)?,
+        };
+
+        if self.settings.semantic_check {
+            self.check_tokens(script, &formatted)?;
+        }
+        Ok(formatted)
+    }
+
+    /// Format the script twice and return the formatted script if the second pass doesn't change
+    /// it, the position of the first changed line otherwise
+    pub fn check_idempotent(&self, script: &str) -> Result<String, FormatError> {
+        let once = self.format(script)?;
+        let twice = self.format(&once)?;
+
+        match once.lines().zip(twice.lines()).position(|(a, b)| a != b) {
+            None if once == twice => Ok(once),
+            position => {
+                let line = position.unwrap_or(once.lines().count().min(twice.lines().count()));
+                Err(FormatError::NotIdempotent {
+                    path: None,
+                    span: Span {
+                        line: line as u64 + 1,
+                        column: 1,
+                    },
+                    message: format!(
+                        "The formatted script changes when formatted again : '{}' becomes '{}'",
+                        once.lines().nth(line).unwrap_or_default(),
+                        twice.lines().nth(line).unwrap_or_default()
+                    ),
+                })
+            }
+        }
+    }
+
+    /// Check that the formatted script holds the same tokens as the original script, the
+    /// whitespaces, the comments and the case of the keywords aside
+    pub fn check_tokens(&self, original: &str, formatted: &str) -> Result<(), FormatError> {
+        check_tokens(self.dialect(), original, formatted)
+    }
+}
+
+/// Format the SQL script in input with the settings in input
+pub fn format_str(settings: &Settings, script: &str) -> Result<String, FormatError> {
+    Formatter::new(settings.clone())?.format(script)
+}
+
+/// Tokens of the script with their text in the script, written instead of the display of the
+/// tokens which doesn't escape the quotes of the strings and of the identifiers
+pub(crate) fn tokenize<'a>(
+    dialect: &dyn Dialect,
+    script: &'a str,
+) -> Result<(Vec<Token>, Vec<&'a str>), FormatError> {
+    let line_starts = line_starts(script);
+    Ok(Tokenizer::new(dialect, script)
+        .tokenize_with_location()?
+        .into_iter()
+        .map(|token| {
+            let start = location_offset(script, &line_starts, token.span.start);
+            let end = location_offset(script, &line_starts, token.span.end);
+            (token.token, script.get(start..end).unwrap_or_default())
+        })
+        .unzip())
+}
+
+/// Resolve the dialect from the setting `database`, fallback on the generic dialect
+fn dialect_from_name(name: &str) -> Box<dyn Dialect + Send + Sync> {
+    match name.to_lowercase().as_str() {
+        "mysql" => Box::new(MySqlDialect {}),
+        "postgresql" | "postgres" | "postgre" => Box::new(PostgreSqlDialect {}),
+        "hive" => Box::new(HiveDialect {}),
+        "sqlite" => Box::new(SQLiteDialect {}),
+        "snowflake" => Box::new(SnowflakeDialect),
+        "redshift" => Box::new(RedshiftSqlDialect {}),
+        "mssql" => Box::new(MsSqlDialect {}),
+        "clickhouse" => Box::new(ClickHouseDialect {}),
+        "bigquery" => Box::new(BigQueryDialect),
+        "ansi" => Box::new(AnsiDialect {}),
+        "duckdb" => Box::new(DuckDbDialect {}),
+        "databricks" => Box::new(DatabricksDialect {}),
+        _ => Box::new(GenericDialect),
+    }
+}
+
+/// Pop the brackets or the braces of the kind in input if they are the last ones opened, and
+/// return the construct enclosing them
+fn close(parentheses: &mut Vec<Parenthesis>, kind: ParenthesisKind) -> Option<Construct> {
+    if parentheses.last()?.kind != kind {
+        return None;
+    }
+    parentheses.pop().map(|parenthesis| parenthesis.construct)
+}
+
+pub fn formater(
+    formatter: &Formatter,
+    script_path: PathBuf,
+    lines: &[(usize, usize)],
+) -> Result<(), FormatError> {
+    let script = read_script(&script_path)?;
+
+    let formated_script =
+        format_script(formatter, &script, lines).map_err(|e| e.with_path(&script_path))?;
+
+    let mut file = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .open(&script_path)
+        .map_err(|e| FormatError::io(&script_path, e))?;
+
+    file.write_all(formated_script.as_bytes())
+        .map_err(|e| FormatError::io(&script_path, e))
+}
+
+/// Format the script without writing it and return true if it's already formatted
+pub fn check_file(
+    formatter: &Formatter,
+    script_path: PathBuf,
+    lines: &[(usize, usize)],
+) -> Result<bool, FormatError> {
+    let script = read_script(&script_path)?;
+
+    let formated_script =
+        format_script(formatter, &script, lines).map_err(|e| e.with_path(&script_path))?;
+    Ok(formated_script == script)
+}
+
+/// Format the script without writing it and return the unified diff between the script and the
+/// formatted script, `None` if it's already formatted
+pub fn diff_file(
+    formatter: &Formatter,
+    script_path: PathBuf,
+    lines: &[(usize, usize)],
+) -> Result<Option<String>, FormatError> {
+    let script = read_script(&script_path)?;
+
+    let formated_script =
+        format_script(formatter, &script, lines).map_err(|e| e.with_path(&script_path))?;
+    if formated_script == script {
+        return Ok(None);
+    }
+
+    Ok(Some(unified_diff(
+        &script_path.display().to_string(),
+        &script,
+        &formated_script,
+    )))
+}
+
+/// Unified diff between the script and the formatted script, with the name in input as header
+pub(crate) fn unified_diff(name: &str, script: &str, formated_script: &str) -> String {
+    TextDiff::from_lines(script, formated_script)
+        .unified_diff()
+        .context_radius(3)
+        .header(name, name)
+        .missing_newline_hint(true)
+        .to_string()
+}
+
+/// Format the script twice without writing it and fail if the second pass changes the result
+pub fn verify_idempotent(formatter: &Formatter, script_path: PathBuf) -> Result<(), FormatError> {
+    let script = read_script(&script_path)?;
+
+    formatter
+        .check_idempotent(&script)
+        .map(|_| ())
+        .map_err(|e| e.with_path(&script_path))
+}
+
+/// Format the whole script if the list of line ranges is empty, only the statements overlapping
+/// these ranges otherwise
+pub(crate) fn format_script(
+    formatter: &Formatter,
+    script: &str,
+    lines: &[(usize, usize)],
+) -> Result<String, FormatError> {
+    if lines.is_empty() {
+        return formatter.format(script);
+    }
+
+    // From the last range, so the lines of the previous ranges are unchanged
+    let mut ranges = lines.to_vec();
+    ranges.sort();
+    let mut script = script.to_string();
+    for (start_line, end_line) in ranges.into_iter().rev() {
+        script = formatter.format_lines(&script, start_line, end_line)?;
+    }
+    Ok(script)
+}
+
+pub(crate) fn read_script(script_path: &Path) -> Result<String, FormatError> {
     let mut script = String::new();
     let mut file = OpenOptions::new()
         .read(true)
-        .open(&script_path)
-        .map_err(|e| format!("{}", e))?;
+        .open(script_path)
+        .map_err(|e| FormatError::io(script_path, e))?;
 
     let _ = file
         .read_to_string(&mut script)
-        .map_err(|e| format!("{}", e))?;
-
-    let dialect =
-        dialect_from_str(&settings.database).unwrap_or(Box::new(GenericDialect::default()));
-
-    let tokens = Tokenizer::new(dialect.as_ref(), &script)
-        .tokenize()
-        .map_err(|e| format!("{}", e))?;
-
-    match process_format(settings, tokens) {
-        Ok(formated_script) => {
-            let mut file = OpenOptions::new()
-                .truncate(true)
-                .write(true)
-                .open(&script_path)
-                .map_err(|e| format!("{}", e))?;
-
-            let _ = file
-                .write(formated_script.as_bytes())
-                .map_err(|e| format!("{}", e))?;
-            Ok(())
-        }
-        Err(error) => Err(error),
-    }
+        .map_err(|e| FormatError::io(script_path, e))?;
+    Ok(script)
 }
 
-fn process_format(settings: &Settings, tokens: Vec<Token>) -> Result<String, String> {
-    let mut indentation = IndentationCount::new(&settings.tabulation_format);
-    let mut buffer = String::new();
-    let mut result = String::new();
-    let mut index = 0usize;
-
-    while index < tokens.len() {
-        match &tokens[index] {
-            Token::Word(word) => {
-                match (
-                    result.ends_with("\n"),
-                    settings.indentation_clauses,
-                    word.keyword,
-                ) {
-                    (true, true, Keyword::SELECT) => {
+impl Formatter {
+    /// Format the tokens in input, the texts are the ones of the tokens in the script
+    pub(crate) fn process_format(
+        &self,
+        tokens: Vec<Token>,
+        texts: &[&str],
+    ) -> Result<String, FormatError> {
+        let settings = &self.settings;
+        let mut indentation = IndentationCount::new(&self.tabulation);
+        let mut parentheses: Vec<Parenthesis> = Vec::new();
+        let mut clause = Construct::Other;
+        let mut clause_level: Option<usize> = None;
+        let mut buffer = String::new();
+        let mut result = String::new();
+        let mut index = 0usize;
+
+        while index < tokens.len() {
+            match &tokens[index] {
+                Token::Word(word) => {
+                    if word.quote_style.is_none()
+                        && let Some(construct) = Construct::from_keyword(word.keyword)
+                    {
+                        clause = construct;
+                    }
+
+                    let clause_phrase = match parentheses.last() {
+                        None => self.match_clause(&tokens, index),
+                        Some(parenthesis) if parenthesis.kind == ParenthesisKind::Subquery => {
+                            self.match_clause(&tokens, index)
+                        }
+                        Some(_) => None,
+                    };
+
+                    if let Some(phrase) = clause_phrase {
+                        result.truncate(result.trim_end_matches([' ', '\t']).len());
+                        match clause_level {
+                            Some(level) => {
+                                if !result.ends_with("\n") {
+                                    buffer.push('\n');
+                                }
+                                indentation.set(level);
+                            }
+                            None => {
+                                if !result.ends_with("\n")
+                                    && !result.ends_with("(")
+                                    && !result.trim_end().is_empty()
+                                {
+                                    buffer.push('\n');
+                                }
+                                clause_level = Some(indentation.get());
+                            }
+                        }
                         buffer.push_str(&indentation.get_string(None));
                         indentation.add();
+
+                        let value = self.keyword_case(&phrase.words.join(" "));
+                        buffer.push_str(&value);
+                        if self.linebreak_after.contains(&normalize_phrase(&value))
+                            || self.linebreak_after.contains("*")
+                        {
+                            buffer.push('\n');
+                        }
+
+                        result.push_str(&buffer);
+                        buffer.clear();
+                        index = phrase.end + 1;
+                        continue;
+                    }
+
+                    if word.keyword == Keyword::NoKeyword || result.ends_with(".") {
+                        if result.ends_with("\n") {
+                            buffer.push_str(&indentation.get_string(None));
+                        }
+                        buffer.push_str(texts[index])
+                    } else {
+                        let (value, end) =
+                            match Phrase::match_longest(&tokens, index, &self.keyword_phrases) {
+                                Some(phrase) => {
+                                    (self.keyword_case(&phrase.words.join(" ")), phrase.end)
+                                }
+                                None => (self.keyword_case(&word.value), index),
+                            };
+
+                        if !result.ends_with("\n")
+                            && (self.linebreak_before.contains(&normalize_phrase(&value))
+                                || self.linebreak_before.contains("*"))
+                        {
+                            result.truncate(result.trim_end_matches([' ', '\t']).len());
+                            buffer.push('\n');
+                        }
+
+                        if result.ends_with("\n") || buffer.ends_with("\n") {
+                            buffer.push_str(&indentation.get_string(None));
+                        }
+
+                        buffer.push_str(&value);
+
+                        // The line break implied by a clause only applies on the clauses, not
+                        // on the same keyword inside a function call (i.e. 'EXTRACT(YEAR FROM d)')
+                        if (self.linebreak_after.contains(&normalize_phrase(&value))
+                            && !self.is_clause(&value))
+                            || self.linebreak_after.contains("*")
+                        {
+                            buffer.push('\n');
+                        }
+                        index = end;
+                    }
+                }
+                Token::EOF => {}
+                Token::Comma => {
+                    let construct = parentheses
+                        .last()
+                        .and_then(|parenthesis| parenthesis.kind.construct())
+                        .unwrap_or(clause);
+
+                    if settings.linebreak_after_comma
+                        && (settings.linebreak_after_comma_in.contains(construct.name())
+                            || settings.linebreak_after_comma_in.contains("*"))
+                    {
+                        buffer.push_str(",\n");
+                    } else {
+                        buffer.push(',');
+                    }
+                }
+                Token::SemiColon => {
+                    match clause_level.take() {
+                        Some(level) => indentation.set(level),
+                        None => indentation.sub(),
                     }
-                    (false, true, Keyword::SELECT) => {
+                    if settings.linebreak_after_semicolon {
+                        buffer.push_str(";\n\n")
+                    } else {
+                        buffer.push(';');
+                    }
+                }
+                Token::LParen => {
+                    let kind = ParenthesisKind::classify(&tokens, index, clause);
+                    let parenthesis = Parenthesis {
+                        kind,
+                        construct: clause,
+                        clause_level: clause_level.take(),
+                        layout: kind.layout(settings),
+                        level: indentation.get(),
+                    };
+
+                    if result.ends_with("\n") {
                         buffer.push_str(&indentation.get_string(None));
+                    }
+
+                    if parenthesis.layout.linebreak {
+                        buffer.push_str("(\n");
+                    } else {
+                        buffer.push('(');
+                    }
+
+                    if parenthesis.layout.indentation {
                         indentation.add();
                     }
-                    (true, true, Keyword::FROM | Keyword::WHERE) => {
-                        buffer.push_str(&indentation.get_string(Some(1)));
+                    parentheses.push(parenthesis);
+                    clause = Construct::Other;
+                }
+                Token::LBrace => {
+                    if result.ends_with("\n") {
+                        buffer.push_str(&indentation.get_string(None));
                     }
-                    (false, true, Keyword::FROM | Keyword::WHERE) => {
-                        buffer.push('\n');
-                        buffer.push_str(&indentation.get_string(Some(1)));
+
+                    if settings.linebreak_after_lbrace {
+                        buffer.push_str("{\n");
+                    } else {
+                        buffer.push('{');
+                    }
+
+                    // The commas between the brackets and the braces don't belong to the clause
+                    parentheses.push(Parenthesis {
+                        kind: ParenthesisKind::Braces,
+                        construct: clause,
+                        clause_level: None,
+                        layout: ParenthesisKind::Braces.layout(settings),
+                        level: indentation.get(),
+                    });
+                    clause = Construct::Other;
+
+                    if settings.indentation_braces {
+                        indentation.add();
                     }
-                    (true, _, _) => {
+                }
+                Token::LBracket => {
+                    if result.ends_with("\n") {
                         buffer.push_str(&indentation.get_string(None));
                     }
-                    (false, _, _) => {}
+
+                    if settings.linebreak_after_lbracket {
+                        buffer.push_str("[\n");
+                    } else {
+                        buffer.push('[');
+                    }
+
+                    parentheses.push(Parenthesis {
+                        kind: ParenthesisKind::Brackets,
+                        construct: clause,
+                        clause_level: None,
+                        layout: ParenthesisKind::Brackets.layout(settings),
+                        level: indentation.get(),
+                    });
+                    clause = Construct::Other;
+
+                    if settings.indentation_brackets {
+                        indentation.add();
+                    }
                 }
+                Token::RParen => match parentheses.pop() {
+                    Some(parenthesis) => {
+                        indentation.set(parenthesis.level);
+                        clause = parenthesis.construct;
+                        clause_level = parenthesis.clause_level;
+                        if parenthesis.layout.linebreak && !result.ends_with("\n") {
+                            buffer.push('\n');
+                        }
 
-                let case = settings.keywords_case.as_str();
-                if word.keyword == Keyword::NoKeyword || result.ends_with(".") {
-                    buffer.push_str(&word.value.clone())
-                } else {
-                    let value: String;
-                    match case {
-                        "lowercase" | "lower" => value = word.value.to_lowercase(),
-                        "uppercase" | "upper" => value = word.value.to_uppercase(),
-                        _ => {
-                            return Err(format!(
-                                "Unsupported case : {} in settings :\n{:?}",
-                                case, settings
-                            ));
+                        if parenthesis.layout.linebreak || result.ends_with("\n") {
+                            buffer.push_str(&indentation.get_string(None));
                         }
+                        buffer.push(')');
                     }
+                    None => {
+                        if settings.linebreak_after_lparenthesis && !result.ends_with("\n") {
+                            buffer.push('\n');
+                        }
 
-                    if !result.ends_with("\n")
-                        && (settings.linebreak_before_keywords.contains(&value)
-                            || settings.linebreak_before_keywords.contains("*"))
-                    {
+                        if settings.indentation_parenthesis {
+                            indentation.sub();
+                        }
+
+                        if result.ends_with("\n") || buffer.ends_with("\n") {
+                            buffer.push_str(&indentation.get_string(None));
+                        }
+                        buffer.push(')');
+                    }
+                },
+                Token::RBrace => {
+                    if let Some(construct) = close(&mut parentheses, ParenthesisKind::Braces) {
+                        clause = construct;
+                    }
+                    if settings.linebreak_after_lbrace && !result.ends_with("\n") {
                         buffer.push('\n');
                     }
 
-                    buffer.push_str(&value);
+                    if settings.indentation_braces {
+                        indentation.sub();
+                    }
 
-                    if settings.linebreak_after_keywords.contains(&value)
-                        || settings.linebreak_after_keywords.contains("*")
-                    {
-                        buffer.push('\n');
+                    if result.ends_with("\n") || buffer.ends_with("\n") {
+                        buffer.push_str(&indentation.get_string(None));
                     }
+                    buffer.push('}');
                 }
-            }
-            Token::EOF => {}
-            Token::Comma => {
-                if settings.linebreak_after_comma {
-                    buffer.push_str(",\n");
-                } else {
-                    buffer.push(',');
-                }
-            }
-            Token::SemiColon => {
-                indentation.sub();
-                if settings.linebreak_after_semicolon {
-                    buffer.push_str(";\n\n")
-                } else {
-                    buffer.push(';');
-                }
-            }
-            Token::LParen => {
-                if settings.linebreak_after_lparenthesis {
-                    buffer.push_str("(\n");
-                } else {
-                    buffer.push('(');
-                }
+                Token::RBracket => {
+                    if let Some(construct) = close(&mut parentheses, ParenthesisKind::Brackets) {
+                        clause = construct;
+                    }
+                    if settings.linebreak_after_lbracket && !result.ends_with("\n") {
+                        buffer.push('\n');
+                    }
 
-                if settings.indentation_parenthesis {
-                    indentation.add();
-                }
-            }
-            Token::LBrace => {
-                if settings.linebreak_after_lbrace {
-                    buffer.push_str("{\n");
-                } else {
-                    buffer.push('{');
-                }
+                    if settings.indentation_brackets {
+                        indentation.sub();
+                    }
 
-                if settings.indentation_braces {
-                    indentation.add();
-                }
-            }
-            Token::LBracket => {
-                if settings.linebreak_after_lbracket {
-                    buffer.push_str("[\n");
-                } else {
-                    buffer.push('[');
+                    if result.ends_with("\n") || buffer.ends_with("\n") {
+                        buffer.push_str(&indentation.get_string(None));
+                    }
+                    buffer.push(']');
                 }
+                Token::Whitespace(whitespace) => match whitespace {
+                    Whitespace::Space | Whitespace::Tab | Whitespace::Newline => {
+                        // The whitespaces of the script are collapsed into a single space or a
+                        // single linebreak, the indentation is always computed from the tokens
+                        let end = whitespace_run_end(&tokens, index);
+                        if result.is_empty() || result.ends_with("\n") {
+                        } else if tokens[index..=end]
+                            .contains(&Token::Whitespace(Whitespace::Newline))
+                        {
+                            result.truncate(result.trim_end_matches([' ', '\t']).len());
+                            buffer.push('\n');
+                        } else if !result.ends_with([' ', '\t']) {
+                            buffer.push(' ');
+                        }
+                        index = end;
+                    }
+                    Whitespace::SingleLineComment { comment, prefix } => {
+                        let text = format!("{}{}", prefix, comment.trim_end());
+                        let trimmed = result.trim_end().len();
+                        let newlines = result[trimmed..].matches('\n').count();
 
-                if settings.indentation_brackets {
-                    indentation.add();
-                }
-            }
-            Token::RParen => {
-                if settings.linebreak_after_lparenthesis && !result.ends_with("\n") {
-                    buffer.push('\n');
-                }
+                        if is_trailing_comment(&tokens, index) {
+                            result.truncate(trimmed);
+                            buffer.push(' ');
+                            buffer.push_str(&text);
+                            buffer.push_str(&"\n".repeat(newlines.max(1)));
+                        } else {
+                            result.truncate(result.trim_end_matches([' ', '\t']).len());
+                            if !result.ends_with("\n") && !result.is_empty() {
+                                buffer.push('\n');
+                            }
 
-                if settings.indentation_parenthesis {
-                    buffer.push_str(&indentation.get_string(Some(1)));
-                    indentation.sub();
-                }
-                buffer.push(')');
-            }
-            Token::RBrace => {
-                if settings.linebreak_after_lbrace && !result.ends_with("\n") {
-                    buffer.push('\n');
-                }
+                            let level = match next_significant_index(&tokens, index) {
+                                Some(next)
+                                    if clause_level.is_some()
+                                        && parentheses.last().is_none_or(|parenthesis| {
+                                            parenthesis.kind == ParenthesisKind::Subquery
+                                        })
+                                        && self.match_clause(&tokens, next).is_some() =>
+                                {
+                                    clause_level.unwrap_or_default()
+                                }
+                                _ => indentation.get(),
+                            };
+                            buffer.push_str(&self.tabulation.repeat(level));
+                            buffer.push_str(&text);
+                            buffer.push('\n');
+                        }
 
-                if settings.indentation_braces {
-                    buffer.push_str(&indentation.get_string(Some(1)));
-                    indentation.sub();
-                }
-                buffer.push('}');
-            }
-            Token::RBracket => {
-                if settings.linebreak_after_lbracket && !result.ends_with("\n") {
-                    buffer.push('\n');
-                }
+                        let verbatim_end = match Directive::parse(comment) {
+                            Some(Directive::Off) => Some(
+                                (index + 1..tokens.len())
+                                    .find(|i| {
+                                        Directive::from_token(&tokens[*i]) == Some(Directive::On)
+                                    })
+                                    .unwrap_or(tokens.len() - 1),
+                            ),
+                            Some(Directive::SkipNextStatement) => {
+                                let start = next_significant_index(&tokens, index)
+                                    .unwrap_or(tokens.len() - 1);
+                                Some(
+                                    (start..tokens.len())
+                                        .find(|i| tokens[*i] == Token::SemiColon)
+                                        .map_or(tokens.len() - 1, |semicolon| semicolon - 1),
+                                )
+                            }
+                            _ => None,
+                        };
 
-                if settings.indentation_brackets {
-                    buffer.push_str(&indentation.get_string(Some(1)));
-                    indentation.sub();
-                }
-                buffer.push(']');
-            }
-            Token::Whitespace(whitespace) => match whitespace {
-                Whitespace::Newline | Whitespace::Tab => {
-                    if !result.ends_with("\n") && !result.ends_with("\t") {
-                        buffer.push_str(&format!("{}", whitespace));
+                        if let Some(end) = verbatim_end {
+                            for text in &texts[index + 1..=end] {
+                                buffer.push_str(text);
+                            }
+                            index = end;
+                        }
                     }
-                }
-                Whitespace::Space => {
-                    if !result.ends_with("\n") && !result.ends_with("\t") {
+                    Whitespace::MultiLineComment(_) => {
+                        if result.ends_with("\n") {
+                            buffer.push_str(&indentation.get_string(None));
+                        }
                         buffer.push_str(&format!("{}", whitespace));
-                    } else {
-                        buffer.push_str(&indentation.get_string(None));
                     }
-                }
+                },
                 _ => {
-                    if !result.ends_with("\n") {
-                        buffer.push('\n');
+                    if result.ends_with("\n") {
+                        buffer.push_str(&indentation.get_string(None));
                     }
-                    buffer.push_str(&format!("{}", whitespace));
-                }
-            },
-            other_token => {
-                if result.ends_with("\n") {
-                    buffer.push_str(&indentation.get_string(None));
+                    buffer.push_str(texts[index]);
                 }
-                buffer.push_str(&format!("{}", other_token));
             }
+
+            if buffer.starts_with('\n') {
+                result.truncate(result.trim_end_matches([' ', '\t']).len());
+            }
+            result.push_str(&buffer);
+            buffer.clear();
+            index += 1;
         }
 
-        result.push_str(&buffer);
-        buffer.clear();
-        index += 1;
+        Ok(result)
     }
-
-    Ok(result)
 }
@@ -0,0 +1,147 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::error::FormatError;
+use crate::resolver::Resolver;
+
+/// Changes of the working tree compared by git
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum GitChanges {
+    /// Changes since the revision (commit, branch, tag) in input
+    Since(String),
+    /// Changes staged in the index
+    Staged,
+}
+
+impl GitChanges {
+    /// Arguments of `git diff` with the options and the paths in input, the revision is followed
+    /// by `--` so that it's never taken as a path
+    fn diff_args<'a>(&'a self, options: &[&'a str], paths: &[&'a str]) -> Vec<&'a str> {
+        let mut args = vec!["diff"];
+        args.extend(options);
+        match self {
+            GitChanges::Since(revision) => args.push(revision.as_str()),
+            GitChanges::Staged => args.push("--cached"),
+        }
+        args.push("--");
+        args.extend(paths);
+        args
+    }
+}
+
+/// Path of a script with its line ranges to format (starting from 1, both included), the whole
+/// script if there's no range
+pub(crate) type ScriptLines = (PathBuf, Vec<(usize, usize)>);
+
+/// SQL scripts (with one of the extensions of their settings) added or modified in the repository
+/// of the current directory, with their changed line ranges (starting from 1, both included) if
+/// `with_lines` is true. The untracked scripts (not ignored) are changes since any revision
+pub(crate) fn changed_scripts(
+    changes: &GitChanges,
+    with_lines: bool,
+    resolver: &Resolver,
+) -> Result<Vec<ScriptLines>, FormatError> {
+    let root = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim_end());
+
+    let changed = git(&changes.diff_args(&["--name-only", "--diff-filter=ACMR", "-z"], &[]))?;
+    let untracked = match changes {
+        GitChanges::Since(_) => git_in(
+            &root,
+            &[
+                "ls-files",
+                "--others",
+                "--exclude-standard",
+                "--full-name",
+                "-z",
+            ],
+        )?,
+        GitChanges::Staged => String::new(),
+    };
+
+    let names = changed
+        .split('\0')
+        .map(|name| (name, false))
+        .chain(untracked.split('\0').map(|name| (name, true)));
+    let mut scripts = Vec::new();
+    for (name, untracked) in names {
+        if name.is_empty() {
+            continue;
+        }
+        let path = root.join(name);
+        if !resolver.is_script(&path)? {
+            continue;
+        }
+
+        // Every line of an untracked script is new
+        let lines = if with_lines && !untracked {
+            // The lines changed in the index wouldn't match the lines of the working copy
+            if *changes == GitChanges::Staged
+                && !git_in(&root, &["diff", "--name-only", "--", name])?.is_empty()
+            {
+                return Err(FormatError::io(
+                    &path,
+                    io::Error::other(
+                        "unstaged changes, the changed lines of the index don't match the \
+                         working copy (stage or stash these changes first)",
+                    ),
+                ));
+            }
+            let lines = changed_lines(&git_in(
+                &root,
+                &changes.diff_args(&["--unified=0"], &[name]),
+            )?);
+            // The lines of the script have only been removed
+            if lines.is_empty() {
+                continue;
+            }
+            lines
+        } else {
+            Vec::new()
+        };
+        scripts.push((path, lines));
+    }
+    Ok(scripts)
+}
+
+/// Line ranges added or modified by the hunks of a diff without context
+fn changed_lines(diff: &str) -> Vec<(usize, usize)> {
+    diff.lines()
+        .filter_map(|line| {
+            // Hunk header : @@ -start[,count] +start[,count] @@
+            let added = line.strip_prefix("@@ ")?.split(' ').nth(1)?;
+            let added = added.strip_prefix('+')?;
+            let (start, count) = match added.split_once(',') {
+                Some((start, count)) => (start.parse::<usize>().ok()?, count.parse().ok()?),
+                None => (added.parse::<usize>().ok()?, 1),
+            };
+            (count > 0).then_some((start, start + count - 1))
+        })
+        .collect()
+}
+
+pub(crate) fn git(args: &[&str]) -> Result<String, FormatError> {
+    git_in(Path::new("."), args)
+}
+
+/// Run git with the arguments in input from the folder in input and return its output
+pub(crate) fn git_in(folder: &Path, args: &[&str]) -> Result<String, FormatError> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(folder)
+        .output()
+        .map_err(|e| FormatError::io(Path::new("git"), e))?;
+
+    if !output.status.success() {
+        return Err(FormatError::Io {
+            path: None,
+            source: Arc::new(io::Error::other(format!(
+                "git {} : {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::FormatError;
+use crate::git::git;
+
+const HOOK_NAME: &str = "pre-commit";
+/// Name of a pre-commit hook found while installing, run before the formatter
+const PREVIOUS_HOOK_NAME: &str = "pre-commit.before-sqlformater";
+/// Line identifying the hooks written by sqlformater
+const HOOK_MARKER: &str = "# Installed by sqlformater";
+
+/// Write the pre-commit hook of the repository of the current directory. It checks the content
+/// staged of the scripts (with one of the extensions in input), or formats and stages them again
+/// if `fix` is true, refusing the scripts partially staged. An existing hook is kept and run first
+pub(crate) fn install(fix: bool, extensions: &[String]) -> Result<PathBuf, FormatError> {
+    let folder = hooks_folder()?;
+    let hook_path = folder.join(HOOK_NAME);
+    let previous_path = folder.join(PREVIOUS_HOOK_NAME);
+
+    if hook_path.is_file() && !is_installed(&hook_path)? {
+        if previous_path.exists() {
+            return Err(FormatError::io(
+                &previous_path,
+                io::Error::other("A previous pre-commit hook is already saved at this path"),
+            ));
+        }
+        fs::rename(&hook_path, &previous_path).map_err(|e| FormatError::io(&hook_path, e))?;
+    }
+
+    fs::create_dir_all(&folder).map_err(|e| FormatError::io(&folder, e))?;
+    fs::write(&hook_path, hook_script(fix, extensions))
+        .map_err(|e| FormatError::io(&hook_path, e))?;
+    set_executable(&hook_path)?;
+    Ok(hook_path)
+}
+
+/// Remove the pre-commit hook written by sqlformater and restore the hook found while installing
+pub(crate) fn uninstall() -> Result<PathBuf, FormatError> {
+    let folder = hooks_folder()?;
+    let hook_path = folder.join(HOOK_NAME);
+    let previous_path = folder.join(PREVIOUS_HOOK_NAME);
+
+    if !hook_path.is_file() || !is_installed(&hook_path)? {
+        return Err(FormatError::io(
+            &hook_path,
+            io::Error::other("The pre-commit hook wasn't installed by sqlformater"),
+        ));
+    }
+
+    fs::remove_file(&hook_path).map_err(|e| FormatError::io(&hook_path, e))?;
+    if previous_path.is_file() {
+        fs::rename(&previous_path, &hook_path).map_err(|e| FormatError::io(&previous_path, e))?;
+    }
+    Ok(hook_path)
+}
+
+fn hooks_folder() -> Result<PathBuf, FormatError> {
+    Ok(PathBuf::from(
+        git(&["rev-parse", "--git-path", "hooks"])?.trim_end(),
+    ))
+}
+
+fn is_installed(hook_path: &Path) -> Result<bool, FormatError> {
+    let content = fs::read_to_string(hook_path).map_err(|e| FormatError::io(hook_path, e))?;
+    Ok(content.lines().any(|line| line.starts_with(HOOK_MARKER)))
+}
+
+fn hook_script(fix: bool, extensions: &[String]) -> String {
+    let pathspecs = extensions
+        .iter()
+        .map(|extension| format!("'*.{}'", extension.trim_start_matches('.')))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let run = if fix {
+        r#"# The unstaged changes of a script would be staged with the formatted script
+partial=$(git --literal-pathspecs diff --name-only -- $files)
+if [ -n "$partial" ]; then
+    echo "sqlformater: stage or stash the other changes of these scripts first :" >&2
+    echo "$partial" >&2
+    exit 1
+fi
+
+"$SQLFORMATER" --staged || exit 1
+git --literal-pathspecs add -- $files"#
+    } else {
+        r#"# The content staged is checked, not the working copy
+status=0
+for file in $files; do
+    git show ":$file" | "$SQLFORMATER" - --stdin-filepath="$file" --check || status=1
+done
+exit $status"#
+    };
+
+    format!(
+        r#"#!/bin/sh
+{HOOK_MARKER} ({mode} mode), remove it with `sqlformater uninstall-hook`
+SQLFORMATER="${{SQLFORMATER:-sqlformater}}"
+
+previous="$(dirname "$0")/{PREVIOUS_HOOK_NAME}"
+if [ -x "$previous" ]; then
+    "$previous" "$@" || exit $?
+fi
+
+files=$(git diff --cached --name-only --diff-filter=ACMR -- {pathspecs})
+[ -z "$files" ] && exit 0
+# One script by line
+set -f
+IFS='
+'
+
+{run}
+"#,
+        mode = if fix { "fix" } else { "check" },
+    )
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<(), FormatError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .map_err(|e| FormatError::io(path, e))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<(), FormatError> {
+    Ok(())
+}
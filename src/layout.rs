@@ -0,0 +1,505 @@
+use sqlparser::ast::{
+    BinaryOperator, Distinct, Expr, GroupByExpr, Join, LimitClause, OrderByKind, Query, Select,
+    SelectFlavor, SelectItem, SetExpr, SetQuantifier, Statement, TableFactor, TableWithJoins,
+    UnaryOperator,
+};
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
+
+use crate::context::Directive;
+use crate::error::FormatError;
+use crate::formater::{Formatter, tokenize};
+use crate::semantic::same_tokens;
+
+/// Indentation unit used by the pretty printer of sqlparser
+const AST_INDENTATION: &str = "  ";
+
+impl Formatter {
+    /// Format the script statement by statement from the AST, the statements that fail to parse
+    /// (or contain comments, which aren't kept in the AST) are formatted by the token engine, as
+    /// well as the statements whose tokens are changed by the AST (i.e. an implicit alias)
+    pub(crate) fn ast_format(
+        &self,
+        tokens: Vec<Token>,
+        texts: &[&str],
+    ) -> Result<String, FormatError> {
+        let mut result = String::new();
+
+        for (statement_tokens, statement_texts) in split_statements(tokens, texts) {
+            let formatted = match self.parse_statement(&statement_tokens) {
+                Some(statement) => {
+                    let mut formatted = self.layout_statement(&statement)?;
+                    if statement_tokens.contains(&Token::SemiColon) {
+                        if self.settings().linebreak_after_semicolon {
+                            formatted.push_str(";\n\n");
+                        } else {
+                            formatted.push(';');
+                        }
+                    }
+                    // Checked even without the setting `semantic_check`, as the AST is only
+                    // meant to change the layout
+                    Some(formatted).filter(|formatted| {
+                        Tokenizer::new(self.dialect(), formatted)
+                            .tokenize()
+                            .is_ok_and(|tokens| same_tokens(&statement_tokens, &tokens))
+                    })
+                }
+                None => None,
+            };
+
+            match formatted {
+                Some(formatted) => {
+                    if !result.is_empty() && !result.ends_with(char::is_whitespace) {
+                        result.push(' ');
+                    }
+                    result.push_str(&formatted);
+                }
+                None => {
+                    let formatted = self.process_format(statement_tokens, statement_texts)?;
+                    if result.ends_with('\n') {
+                        result.push_str(formatted.trim_start_matches('\n'));
+                    } else {
+                        result.push_str(&formatted);
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn parse_statement(&self, tokens: &[Token]) -> Option<Statement> {
+        let mut significant = 0usize;
+        for token in tokens {
+            match token {
+                Token::Whitespace(Whitespace::Space | Whitespace::Tab | Whitespace::Newline)
+                | Token::SemiColon
+                | Token::EOF => {}
+                Token::Whitespace(_) => return None,
+                _ => significant += 1,
+            }
+        }
+        if significant == 0 {
+            return None;
+        }
+
+        let mut statements = Parser::new(self.dialect())
+            .with_tokens(tokens.to_vec())
+            .parse_statements()
+            .ok()?;
+
+        match statements.len() {
+            1 => statements.pop(),
+            _ => None,
+        }
+    }
+
+    /// Pretty print the statement, then apply the case of the keywords and the tabulation format
+    fn layout_statement(&self, statement: &Statement) -> Result<String, FormatError> {
+        let pretty = match statement {
+            Statement::Query(query) => layout_query(query, 0),
+            other_statement => format!("{:#}", other_statement),
+        };
+        let (tokens, texts) = tokenize(self.dialect(), &pretty)?;
+        let mut result = String::new();
+        let mut line_start = true;
+        let mut leading_spaces = 0usize;
+        let mut previous: Option<&Token> = None;
+
+        for (token, text) in tokens.iter().zip(texts) {
+            match token {
+                Token::Whitespace(Whitespace::Newline) => {
+                    result.truncate(result.trim_end_matches([' ', '\t']).len());
+                    result.push('\n');
+                    line_start = true;
+                    leading_spaces = 0;
+                }
+                Token::Whitespace(Whitespace::Space) if line_start => leading_spaces += 1,
+                Token::EOF => {}
+                other_token => {
+                    if line_start {
+                        let level = leading_spaces / AST_INDENTATION.len();
+                        result.push_str(&self.tabulation().repeat(level));
+                        line_start = false;
+                    }
+                    match other_token {
+                        Token::Word(word)
+                            if word.keyword != Keyword::NoKeyword
+                                && word.quote_style.is_none()
+                                && previous != Some(&Token::Period) =>
+                        {
+                            result.push_str(&self.keyword_case(&word.value));
+                        }
+                        _ => result.push_str(text),
+                    }
+                }
+            }
+            if !matches!(token, Token::Whitespace(_)) {
+                previous = Some(token);
+            }
+        }
+        result.truncate(result.trim_end_matches([' ', '\t']).len());
+
+        Ok(result)
+    }
+}
+
+fn indent(level: usize) -> String {
+    AST_INDENTATION.repeat(level)
+}
+
+/// Pretty printed node indented at the level in input
+fn layout_fallback(node: &impl std::fmt::Display, level: usize) -> String {
+    format!("{:#}", node)
+        .lines()
+        .map(|line| format!("{}{}", indent(level), line))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Layout of a query, each clause starts a new line and its content is indented
+fn layout_query(query: &Query, level: usize) -> String {
+    if query.fetch.is_some()
+        || !query.locks.is_empty()
+        || query.for_clause.is_some()
+        || query.settings.is_some()
+        || query.format_clause.is_some()
+        || !query.pipe_operators.is_empty()
+    {
+        return layout_fallback(query, level);
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+
+    if let Some(with) = &query.with {
+        let mut clause = format!("{}WITH", indent(level));
+        if with.recursive {
+            clause.push_str(" RECURSIVE");
+        }
+        lines.push(clause);
+
+        let ctes = with
+            .cte_tables
+            .iter()
+            .map(|cte| match cte.materialized {
+                None if cte.from.is_none() => format!(
+                    "{}{} AS (\n{}\n{})",
+                    indent(level + 1),
+                    cte.alias,
+                    layout_query(&cte.query, level + 2),
+                    indent(level + 1)
+                ),
+                _ => layout_fallback(cte, level + 1),
+            })
+            .collect::<Vec<String>>();
+        lines.push(ctes.join(",\n"));
+    }
+
+    lines.push(layout_set_expr(&query.body, level));
+
+    if let Some(order_by) = &query.order_by {
+        match (&order_by.kind, &order_by.interpolate) {
+            (OrderByKind::Expressions(exprs), None) => {
+                lines.push(format!("{}ORDER BY", indent(level)));
+                lines.push(layout_list(exprs, level + 1));
+            }
+            _ => lines.push(format!("{}{}", indent(level), order_by)),
+        }
+    }
+
+    match &query.limit_clause {
+        Some(LimitClause::LimitOffset {
+            limit,
+            offset,
+            limit_by,
+        }) if limit_by.is_empty() => {
+            if let Some(limit) = limit {
+                lines.push(format!("{}LIMIT {}", indent(level), limit));
+            }
+            if let Some(offset) = offset {
+                lines.push(format!("{}{}", indent(level), offset));
+            }
+        }
+        Some(limit_clause) => lines.push(format!(
+            "{}{}",
+            indent(level),
+            limit_clause.to_string().trim()
+        )),
+        None => {}
+    }
+
+    lines.join("\n")
+}
+
+fn layout_set_expr(set_expr: &SetExpr, level: usize) -> String {
+    match set_expr {
+        SetExpr::Select(select) => layout_select(select, level),
+        SetExpr::Query(query) => format!(
+            "{}(\n{}\n{})",
+            indent(level),
+            layout_query(query, level + 1),
+            indent(level)
+        ),
+        SetExpr::SetOperation {
+            op,
+            set_quantifier,
+            left,
+            right,
+        } => {
+            let operator = match set_quantifier {
+                SetQuantifier::None => op.to_string(),
+                quantifier => format!("{} {}", op, quantifier),
+            };
+            format!(
+                "{}\n{}{}\n{}",
+                layout_set_expr(left, level),
+                indent(level),
+                operator,
+                layout_set_expr(right, level)
+            )
+        }
+        other => layout_fallback(other, level),
+    }
+}
+
+fn layout_select(select: &Select, level: usize) -> String {
+    if select.top.is_some()
+        || select.exclude.is_some()
+        || select.into.is_some()
+        || !select.lateral_views.is_empty()
+        || select.prewhere.is_some()
+        || !select.cluster_by.is_empty()
+        || !select.distribute_by.is_empty()
+        || !select.sort_by.is_empty()
+        || !select.named_window.is_empty()
+        || select.value_table_mode.is_some()
+        || select.connect_by.is_some()
+        || select.flavor != SelectFlavor::Standard
+    {
+        return layout_fallback(select, level);
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+
+    let mut clause = format!("{}SELECT", indent(level));
+    match &select.distinct {
+        Some(Distinct::Distinct) => clause.push_str(" DISTINCT"),
+        Some(distinct) => clause.push_str(&format!(" {}", distinct)),
+        None => {}
+    }
+    lines.push(clause);
+    lines.push(
+        select
+            .projection
+            .iter()
+            .map(|item| {
+                format!(
+                    "{}{}",
+                    indent(level + 1),
+                    layout_select_item(item, level + 1)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",\n"),
+    );
+
+    if !select.from.is_empty() {
+        lines.push(format!("{}FROM", indent(level)));
+        lines.push(
+            select
+                .from
+                .iter()
+                .map(|table| layout_table_with_joins(table, level + 1))
+                .collect::<Vec<String>>()
+                .join(",\n"),
+        );
+    }
+
+    if let Some(selection) = &select.selection {
+        lines.push(format!("{}WHERE", indent(level)));
+        lines.push(layout_condition(selection, level + 1));
+    }
+
+    match &select.group_by {
+        GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => {
+            if !exprs.is_empty() {
+                lines.push(format!("{}GROUP BY", indent(level)));
+                lines.push(layout_list(exprs, level + 1));
+            }
+        }
+        group_by => lines.push(format!("{}{}", indent(level), group_by)),
+    }
+
+    if let Some(having) = &select.having {
+        lines.push(format!("{}HAVING", indent(level)));
+        lines.push(layout_condition(having, level + 1));
+    }
+
+    if let Some(qualify) = &select.qualify {
+        lines.push(format!("{}QUALIFY", indent(level)));
+        lines.push(layout_condition(qualify, level + 1));
+    }
+
+    lines.join("\n")
+}
+
+fn layout_list(items: &[impl std::fmt::Display], level: usize) -> String {
+    items
+        .iter()
+        .map(|item| format!("{}{}", indent(level), item))
+        .collect::<Vec<String>>()
+        .join(",\n")
+}
+
+fn layout_select_item(item: &SelectItem, level: usize) -> String {
+    match item {
+        SelectItem::UnnamedExpr(expr) => layout_expr(expr, level),
+        SelectItem::ExprWithAlias { expr, alias } => {
+            format!("{} AS {}", layout_expr(expr, level), alias)
+        }
+        other => other.to_string(),
+    }
+}
+
+fn layout_table_with_joins(table: &TableWithJoins, level: usize) -> String {
+    let mut result = format!(
+        "{}{}",
+        indent(level),
+        layout_table_factor(&table.relation, level)
+    );
+    for join in &table.joins {
+        result.push('\n');
+        result.push_str(&indent(level));
+        result.push_str(&layout_join(join, level));
+    }
+    result
+}
+
+fn layout_table_factor(table_factor: &TableFactor, level: usize) -> String {
+    match table_factor {
+        TableFactor::Derived {
+            lateral,
+            subquery,
+            alias,
+        } => {
+            let mut result = String::new();
+            if *lateral {
+                result.push_str("LATERAL ");
+            }
+            result.push_str(&format!(
+                "(\n{}\n{})",
+                layout_query(subquery, level + 1),
+                indent(level)
+            ));
+            if let Some(alias) = alias {
+                result.push_str(&format!(" AS {}", alias));
+            }
+            result
+        }
+        other => other.to_string(),
+    }
+}
+
+fn layout_join(join: &Join, level: usize) -> String {
+    let join_string = join.to_string();
+    let join_string = join_string.trim_start();
+    match &join.relation {
+        TableFactor::Derived { .. } => join_string.replacen(
+            &join.relation.to_string(),
+            &layout_table_factor(&join.relation, level),
+            1,
+        ),
+        _ => join_string.to_string(),
+    }
+}
+
+/// Layout of a condition, each top-level AND/OR starts a new line
+fn layout_condition(expr: &Expr, level: usize) -> String {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: op @ (BinaryOperator::And | BinaryOperator::Or),
+            right,
+        } => format!(
+            "{}\n{}{} {}",
+            layout_condition(left, level),
+            indent(level),
+            op,
+            layout_expr(right, level)
+        ),
+        other => format!("{}{}", indent(level), layout_expr(other, level)),
+    }
+}
+
+/// Layout of an expression, kept on one line except its subqueries
+fn layout_expr(expr: &Expr, level: usize) -> String {
+    let subquery =
+        |query: &Query| format!("(\n{}\n{})", layout_query(query, level + 1), indent(level));
+
+    match expr {
+        Expr::Subquery(query) => subquery(query),
+        Expr::InSubquery {
+            expr,
+            subquery: query,
+            negated,
+        } => format!(
+            "{} {}IN {}",
+            layout_expr(expr, level),
+            if *negated { "NOT " } else { "" },
+            subquery(query)
+        ),
+        Expr::Exists {
+            subquery: query,
+            negated,
+        } => format!(
+            "{}EXISTS {}",
+            if *negated { "NOT " } else { "" },
+            subquery(query)
+        ),
+        Expr::BinaryOp { left, op, right } => format!(
+            "{} {} {}",
+            layout_expr(left, level),
+            op,
+            layout_expr(right, level)
+        ),
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => format!("NOT {}", layout_expr(expr, level)),
+        Expr::Nested(expr) => format!("({})", layout_expr(expr, level)),
+        other => other.to_string(),
+    }
+}
+
+/// Split the tokens (and their texts) after each semicolon, each part keeps its leading whitespaces. The regions
+/// disabled by the directive `-- sqlformater: off` are kept in a single part
+fn split_statements<'a, 'b>(
+    tokens: Vec<Token>,
+    texts: &'b [&'a str],
+) -> Vec<(Vec<Token>, &'b [&'a str])> {
+    let mut statements = Vec::new();
+    let mut current: Vec<Token> = Vec::new();
+    let mut start = 0usize;
+    let mut disabled = false;
+
+    for token in tokens {
+        match Directive::from_token(&token) {
+            Some(Directive::Off) => disabled = true,
+            Some(Directive::On) => disabled = false,
+            _ => {}
+        }
+        let end_of_statement = token == Token::SemiColon && !disabled;
+        current.push(token);
+        if end_of_statement {
+            let end = start + current.len();
+            statements.push((std::mem::take(&mut current), &texts[start..end]));
+            start = end;
+        }
+    }
+
+    if !current.is_empty() {
+        statements.push((current, &texts[start..]));
+    }
+    statements
+}
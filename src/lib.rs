@@ -1,3 +1,20 @@
 pub mod cli;
+mod context;
+mod error;
 mod formater;
+mod git;
+mod hook;
+mod layout;
+mod lsp;
+mod range;
+mod resolver;
+mod semantic;
 mod settings;
+mod walk;
+mod watch;
+
+pub use error::{FormatError, Span};
+pub use formater::{Formatter, format_str};
+pub use settings::{
+    ParenthesisLayout, PartialParenthesisLayout, PartialSettings, Settings, SettingsOverride,
+};
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{Formatting, OnTypeFormatting, RangeFormatting, Request as LspRequest};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentOnTypeFormattingOptions, OneOf, Position,
+    PublishDiagnosticsParams, Range as LspRange, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Uri,
+};
+use mylog::error;
+use sqlparser::tokenizer::Tokenizer;
+
+use crate::error::FormatError;
+use crate::formater::Formatter;
+use crate::range::line_starts;
+use crate::resolver::Resolver;
+
+/// Run the language server over stdio until the client shuts it down
+pub fn main(resolver: &Resolver) -> Result<(), FormatError> {
+    let (connection, io_threads) = Connection::stdio();
+    run(resolver, &connection)?;
+    drop(connection);
+    io_threads.join().map_err(protocol_error)
+}
+
+/// Serve the requests of the client on the connection in input until it shuts the server down,
+/// each document formatted with the settings resolved from its path
+pub fn run(resolver: &Resolver, connection: &Connection) -> Result<(), FormatError> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: ";".to_string(),
+            more_trigger_character: None,
+        }),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).unwrap_or_default())
+        .map_err(protocol_error)?;
+
+    // Text of the opened documents by URI
+    let mut documents: HashMap<String, String> = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(protocol_error)?
+                {
+                    return Ok(());
+                }
+                let response = handle_request(resolver, &documents, request);
+                connection
+                    .sender
+                    .send(Message::Response(response))
+                    .map_err(protocol_error)?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = handle_notification(&mut documents, notification) {
+                    let diagnostics = PublishDiagnosticsParams {
+                        diagnostics: documents
+                            .get(uri.as_str())
+                            .zip(document_formatter(resolver, &uri))
+                            .map(|(text, formatter)| diagnostics(&formatter, text))
+                            .unwrap_or_default(),
+                        uri,
+                        version: None,
+                    };
+                    connection
+                        .sender
+                        .send(Message::Notification(Notification::new(
+                            PublishDiagnostics::METHOD.to_string(),
+                            diagnostics,
+                        )))
+                        .map_err(protocol_error)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(
+    resolver: &Resolver,
+    documents: &HashMap<String, String>,
+    request: Request,
+) -> Response {
+    let id = request.id.clone();
+    let text = |uri: &Uri| documents.get(uri.as_str());
+    let formatter = |uri: &Uri| document_formatter(resolver, uri);
+    let edits = match request.method.as_str() {
+        Formatting::METHOD => request
+            .extract::<<Formatting as LspRequest>::Params>(Formatting::METHOD)
+            .map(|(_, params)| {
+                let uri = &params.text_document.uri;
+                format_document(&*formatter(uri)?, text(uri))
+            }),
+        RangeFormatting::METHOD => request
+            .extract::<<RangeFormatting as LspRequest>::Params>(RangeFormatting::METHOD)
+            .map(|(_, params)| {
+                let uri = &params.text_document.uri;
+                let text = text(uri)?;
+                let range = offset(text, params.range.start)..offset(text, params.range.end);
+                format_range(&*formatter(uri)?, text, range)
+            }),
+        OnTypeFormatting::METHOD => request
+            .extract::<<OnTypeFormatting as LspRequest>::Params>(OnTypeFormatting::METHOD)
+            .map(|(_, params)| {
+                // Format the statement ended by the character typed before the position
+                let uri = &params.text_document_position.text_document.uri;
+                let text = text(uri)?;
+                let end = offset(text, params.text_document_position.position);
+                let start = text[..end].char_indices().next_back().map_or(0, |(i, _)| i);
+                format_range(&*formatter(uri)?, text, start..end)
+            }),
+        method => {
+            return Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("Unsupported method : {}", method),
+            );
+        }
+    };
+
+    match edits {
+        Ok(edits) => Response::new_ok(id, edits),
+        Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+    }
+}
+
+/// Update the documents from the notification and return the document whose diagnostics changed
+fn handle_notification(
+    documents: &mut HashMap<String, String>,
+    notification: Notification,
+) -> Option<Uri> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params = notification
+                .extract::<<DidOpenTextDocument as LspNotification>::Params>(
+                    DidOpenTextDocument::METHOD,
+                )
+                .ok()?;
+            documents.insert(
+                params.text_document.uri.as_str().to_string(),
+                params.text_document.text,
+            );
+            Some(params.text_document.uri)
+        }
+        DidChangeTextDocument::METHOD => {
+            let params = notification
+                .extract::<<DidChangeTextDocument as LspNotification>::Params>(
+                    DidChangeTextDocument::METHOD,
+                )
+                .ok()?;
+            let change = params.content_changes.into_iter().last()?;
+            documents.insert(params.text_document.uri.as_str().to_string(), change.text);
+            Some(params.text_document.uri)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params = notification
+                .extract::<<DidCloseTextDocument as LspNotification>::Params>(
+                    DidCloseTextDocument::METHOD,
+                )
+                .ok()?;
+            documents.remove(params.text_document.uri.as_str());
+            Some(params.text_document.uri)
+        }
+        _ => None,
+    }
+}
+
+/// Formatter of the document, from the settings of its path for the `file` URIs and from the
+/// settings of the current directory otherwise, `None` if its settings are invalid
+fn document_formatter(resolver: &Resolver, uri: &Uri) -> Option<Arc<Formatter>> {
+    let formatter = match document_path(uri) {
+        Some(path) => resolver.formatter(&path),
+        None => resolver.default_formatter(),
+    };
+    formatter.map_err(|error| error!("{}", error)).ok()
+}
+
+/// Path of the document of a `file` URI
+fn document_path(uri: &Uri) -> Option<PathBuf> {
+    if !uri.scheme()?.as_str().eq_ignore_ascii_case("file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string().ok()?;
+    // The URIs of the Windows paths start with a slash before the drive letter
+    if cfg!(windows) {
+        return Some(PathBuf::from(path.trim_start_matches('/')));
+    }
+    Some(PathBuf::from(path.as_ref()))
+}
+
+/// Edits replacing the whole document by the formatted document, `None` if it can't be formatted
+fn format_document(formatter: &Formatter, text: Option<&String>) -> Option<Vec<TextEdit>> {
+    let text = text?;
+    match formatter.format(text) {
+        Ok(formatted) if formatted == *text => Some(Vec::new()),
+        Ok(formatted) => Some(vec![TextEdit {
+            range: LspRange {
+                start: Position::new(0, 0),
+                end: end_position(text),
+            },
+            new_text: formatted,
+        }]),
+        Err(error) => {
+            error!("{}", error);
+            None
+        }
+    }
+}
+
+/// Edits replacing the statements overlapping the byte range by the formatted statements
+fn format_range(formatter: &Formatter, text: &str, range: Range<usize>) -> Option<Vec<TextEdit>> {
+    match formatter.format_span(text, range) {
+        Ok(Some((span, formatted))) if formatted != text[span.clone()] => Some(vec![TextEdit {
+            range: LspRange {
+                start: offset_position(text, span.start),
+                end: offset_position(text, span.end),
+            },
+            new_text: formatted,
+        }]),
+        Ok(_) => Some(Vec::new()),
+        Err(error) => {
+            error!("{}", error);
+            None
+        }
+    }
+}
+
+/// Errors raised by the tokenizer on the document
+fn diagnostics(formatter: &Formatter, text: &str) -> Vec<Diagnostic> {
+    match Tokenizer::new(formatter.dialect(), text).tokenize() {
+        Ok(_) => Vec::new(),
+        Err(error) => {
+            let position = position(text, error.location.line, error.location.column);
+            vec![Diagnostic {
+                range: LspRange {
+                    start: position,
+                    end: position,
+                },
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("sqlformater".to_string()),
+                message: error.message,
+                ..Default::default()
+            }]
+        }
+    }
+}
+
+/// Position of the line and the column in input (starting from 1, the column counts the
+/// characters) in UTF-16 code units, as expected by the clients
+fn position(text: &str, line: u64, column: u64) -> Position {
+    let line = line.saturating_sub(1) as usize;
+    let character = text
+        .split('\n')
+        .nth(line)
+        .unwrap_or_default()
+        .chars()
+        .take(column.saturating_sub(1) as usize)
+        .map(char::len_utf16)
+        .sum::<usize>();
+    Position::new(line as u32, character as u32)
+}
+
+/// Byte offset of the position in input
+fn offset(text: &str, position: Position) -> usize {
+    let line_starts = line_starts(text);
+    let Some(line_start) = line_starts.get(position.line as usize) else {
+        return text.len();
+    };
+
+    let mut character = 0;
+    for (index, char) in text[*line_start..].char_indices() {
+        if character >= position.character as usize || char == '\n' {
+            return line_start + index;
+        }
+        character += char.len_utf16();
+    }
+    text.len()
+}
+
+/// Position of the byte offset in input
+fn offset_position(text: &str, offset: usize) -> Position {
+    end_position(&text[..offset])
+}
+
+fn end_position(text: &str) -> Position {
+    let line = text.matches('\n').count();
+    let character = text
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .encode_utf16()
+        .count();
+    Position::new(line as u32, character as u32)
+}
+
+fn protocol_error(error: impl Display) -> FormatError {
+    FormatError::Io {
+        path: None,
+        source: Arc::new(io::Error::other(error.to_string())),
+    }
+}
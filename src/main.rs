@@ -1,7 +1,9 @@
 use std::env;
-fn main() {
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
     use sqlformater::cli;
 
-    let args: Vec<String> = env::args().collect();
-    cli::main(args);
+    let args: Vec<String> = env::args().skip(1).collect();
+    ExitCode::from(cli::main(args))
 }
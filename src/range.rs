@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use sqlparser::tokenizer::{Location, Token, Tokenizer};
+
+use crate::context::{Directive, is_significant};
+use crate::error::FormatError;
+use crate::formater::Formatter;
+
+impl Formatter {
+    /// Format the statements between the lines in input (starting from 1, both included) and
+    /// leave the rest of the script byte-identical
+    pub fn format_lines(
+        &self,
+        script: &str,
+        start_line: usize,
+        end_line: usize,
+    ) -> Result<String, FormatError> {
+        let line_starts = line_starts(script);
+        let line_offset = |line: usize| {
+            line_starts
+                .get(line.saturating_sub(1))
+                .copied()
+                .unwrap_or(script.len())
+        };
+
+        self.format_range(script, line_offset(start_line)..line_offset(end_line + 1))
+    }
+
+    /// Format the statements overlapping the byte range in input and leave the rest of the script
+    /// byte-identical
+    pub fn format_range(&self, script: &str, range: Range<usize>) -> Result<String, FormatError> {
+        Ok(match self.format_span(script, range)? {
+            Some((span, formatted)) => {
+                format!(
+                    "{}{}{}",
+                    &script[..span.start],
+                    formatted,
+                    &script[span.end..]
+                )
+            }
+            None => script.to_string(),
+        })
+    }
+
+    /// Expand the byte range in input to whole statements and return the byte range of these
+    /// statements with their formatted text, `None` if the range doesn't overlap any statement.
+    /// The statements protected by a directive (`off` or `skip-next-statement`) are left aside
+    pub(crate) fn format_span(
+        &self,
+        script: &str,
+        range: Range<usize>,
+    ) -> Result<Option<(Range<usize>, String)>, FormatError> {
+        let line_starts = line_starts(script);
+        let offset = |location: Location| location_offset(script, &line_starts, location);
+
+        // Statements with true if a directive protects them
+        let mut statements: Vec<(Range<usize>, bool)> = Vec::new();
+        let mut current: Option<(Range<usize>, bool)> = None;
+        let mut disabled = false;
+        let mut skip_next = false;
+        for token in Tokenizer::new(self.dialect(), script).tokenize_with_location()? {
+            if !is_significant(&token.token) {
+                match Directive::from_token(&token.token) {
+                    Some(Directive::Off) => disabled = true,
+                    Some(Directive::On) => disabled = false,
+                    Some(Directive::SkipNextStatement) => skip_next = current.is_none(),
+                    None => {}
+                }
+                continue;
+            }
+            let end = offset(token.span.end);
+            let (statement, _) = current.get_or_insert_with(|| {
+                let protected = disabled || std::mem::take(&mut skip_next);
+                (offset(token.span.start)..end, protected)
+            });
+            statement.end = end;
+            if token.token == Token::SemiColon {
+                statements.extend(current.take());
+            }
+        }
+        statements.extend(current);
+
+        let overlapping = statements
+            .into_iter()
+            .filter(|(statement, protected)| {
+                !protected
+                    && statement.start < range.end.max(range.start + 1)
+                    && range.start < statement.end
+            })
+            .map(|(statement, _)| statement)
+            .collect::<Vec<Range<usize>>>();
+        let (Some(first), Some(last)) = (overlapping.first(), overlapping.last()) else {
+            return Ok(None);
+        };
+        let span = first.start..last.end;
+
+        // The lines of the formatted statements are indented as the line of the first statement
+        let line_start = script[..span.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line = &script[line_start..span.start];
+        let indentation = &line[..line.len() - line.trim_start().len()];
+
+        let formatted = self.format(&script[span.clone()])?;
+        let formatted = formatted.trim_end();
+        if indentation.is_empty() {
+            return Ok(Some((span, formatted.to_string())));
+        }
+
+        let inner_lines = inner_lines(self, formatted)?;
+        let formatted = formatted
+            .split('\n')
+            .enumerate()
+            .map(|(index, line)| {
+                if index == 0 || line.is_empty() || inner_lines.contains(&(index as u64 + 1)) {
+                    line.to_string()
+                } else {
+                    format!("{}{}", indentation, line)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        Ok(Some((span, formatted)))
+    }
+}
+
+/// Byte offset of the start of each line
+pub(crate) fn line_starts(script: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(script.match_indices('\n').map(|(index, _)| index + 1))
+        .collect()
+}
+
+/// Byte offset of the location in input (the column counts the characters)
+pub(crate) fn location_offset(script: &str, line_starts: &[usize], location: Location) -> usize {
+    let Some(line_start) = line_starts.get((location.line as usize).saturating_sub(1)) else {
+        return script.len();
+    };
+    line_start
+        + script[*line_start..]
+            .chars()
+            .take((location.column as usize).saturating_sub(1))
+            .map(char::len_utf8)
+            .sum::<usize>()
+}
+
+/// Lines (starting from 1) starting inside a token, i.e. a string or a comment on several lines
+fn inner_lines(formatter: &Formatter, script: &str) -> Result<HashSet<u64>, FormatError> {
+    Ok(Tokenizer::new(formatter.dialect(), script)
+        .tokenize_with_location()?
+        .into_iter()
+        .flat_map(|token| {
+            // A token ending at the start of a line (i.e. a single line comment) doesn't hold it
+            let end = match token.span.end.column {
+                1 => token.span.end.line - 1,
+                _ => token.span.end.line,
+            };
+            token.span.start.line + 1..=end
+        })
+        .collect())
+}
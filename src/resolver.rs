@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use globset::GlobSet;
+
+use crate::error::FormatError;
+use crate::formater::Formatter;
+use crate::settings::{Settings, discover_settings};
+use crate::walk::{glob_set, is_script};
+
+/// Formatters of the scripts, from the settings discovered in the folder of each script (or the
+/// settings in input) with the overrides matching the script
+#[derive(Debug)]
+pub(crate) struct Resolver {
+    /// Scope of the scripts without configuration file
+    fallback: Arc<Scope>,
+    /// Look for the configuration files of the folder of each script
+    discover: bool,
+    /// Disable the setting `semantic_check` of every scope
+    no_semantic_check: bool,
+    /// Scopes by folder of the scripts
+    scopes: Mutex<HashMap<PathBuf, Arc<Scope>>>,
+}
+
+/// Settings shared by the scripts of some folders
+#[derive(Debug)]
+struct Scope {
+    settings: Settings,
+    /// Folder the globs of the overrides are relative to
+    root: PathBuf,
+    overrides: Vec<GlobSet>,
+    /// Formatters by indexes of the matching overrides
+    formatters: Mutex<HashMap<Vec<usize>, Arc<Formatter>>>,
+}
+
+impl Resolver {
+    /// Resolver using the settings in input for every script if `discover` is false, for the
+    /// scripts without configuration file otherwise
+    pub(crate) fn new(
+        mut settings: Settings,
+        discover: bool,
+        no_semantic_check: bool,
+    ) -> Result<Resolver, FormatError> {
+        if no_semantic_check {
+            settings.semantic_check = false;
+        }
+
+        let resolver = Resolver {
+            fallback: Arc::new(Scope::new(settings, current_dir())?),
+            discover,
+            no_semantic_check,
+            scopes: Mutex::new(HashMap::new()),
+        };
+        // Report the invalid configuration files of the current directory before any script
+        resolver.scope(&current_dir())?;
+        Ok(resolver)
+    }
+
+    /// Settings of the scripts of the current directory
+    pub(crate) fn settings(&self) -> Result<Settings, FormatError> {
+        Ok(self.scope(&current_dir())?.settings.clone())
+    }
+
+    /// Formatter of the scripts of the current directory, without override
+    pub(crate) fn default_formatter(&self) -> Result<Arc<Formatter>, FormatError> {
+        self.scope(&current_dir())?.formatter_with(Vec::new())
+    }
+
+    /// Formatter of the script at the path in input, which may not exist
+    pub(crate) fn formatter(&self, path: &Path) -> Result<Arc<Formatter>, FormatError> {
+        let path = current_dir().join(path);
+        let folder = path.parent().unwrap_or(&path);
+        self.scope(folder)?
+            .formatter(&path)
+            .map_err(|e| e.with_path(&path))
+    }
+
+    /// Return true if the file at the path in input has one of the extensions of its settings
+    pub(crate) fn is_script(&self, path: &Path) -> Result<bool, FormatError> {
+        let path = current_dir().join(path);
+        let folder = path.parent().unwrap_or(&path);
+        Ok(is_script(&path, &self.scope(folder)?.settings.extensions))
+    }
+
+    fn scope(&self, folder: &Path) -> Result<Arc<Scope>, FormatError> {
+        if !self.discover {
+            return Ok(self.fallback.clone());
+        }
+
+        let mut scopes = self
+            .scopes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(scope) = scopes.get(folder) {
+            return Ok(scope.clone());
+        }
+
+        let scope = match discover_settings(folder)? {
+            Some((mut settings, root)) => {
+                if self.no_semantic_check {
+                    settings.semantic_check = false;
+                }
+                Arc::new(Scope::new(settings, root)?)
+            }
+            None => self.fallback.clone(),
+        };
+        scopes.insert(folder.to_path_buf(), scope.clone());
+        Ok(scope)
+    }
+}
+
+impl Scope {
+    fn new(settings: Settings, root: PathBuf) -> Result<Scope, FormatError> {
+        let mut formatters = HashMap::new();
+        formatters.insert(Vec::new(), Arc::new(Formatter::new(settings.clone())?));
+
+        let mut overrides = Vec::new();
+        for settings_override in &settings.overrides {
+            overrides.push(glob_set(&settings_override.files)?);
+            // Report the invalid overrides before formatting any script
+            Formatter::new(settings.with_override(settings_override))?;
+        }
+
+        Ok(Scope {
+            settings,
+            root,
+            overrides,
+            formatters: Mutex::new(formatters),
+        })
+    }
+
+    /// Formatter of the script at the path in input, with the overrides matching its path
+    /// relative to the root folder or its file name
+    fn formatter(&self, path: &Path) -> Result<Arc<Formatter>, FormatError> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let name = path.file_name().map(Path::new).unwrap_or(path);
+        let matched = self
+            .overrides
+            .iter()
+            .enumerate()
+            .filter(|(_, globs)| globs.is_match(relative) || globs.is_match(name))
+            .map(|(index, _)| index)
+            .collect();
+
+        self.formatter_with(matched)
+    }
+
+    fn formatter_with(&self, overrides: Vec<usize>) -> Result<Arc<Formatter>, FormatError> {
+        let mut formatters = self
+            .formatters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(formatter) = formatters.get(&overrides) {
+            return Ok(formatter.clone());
+        }
+
+        let mut settings = self.settings.clone();
+        for index in &overrides {
+            settings = settings.with_override(&self.settings.overrides[*index]);
+        }
+        let formatter = Arc::new(Formatter::new(settings)?);
+        formatters.insert(overrides, formatter.clone());
+        Ok(formatter)
+    }
+}
+
+fn current_dir() -> PathBuf {
+    env::current_dir().unwrap_or_default()
+}
@@ -0,0 +1,119 @@
+use sqlparser::dialect::Dialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, TokenWithSpan, Tokenizer};
+
+use crate::context::is_significant;
+use crate::error::{FormatError, Span};
+
+/// Check that the formatted script holds the same tokens as the original script, the whitespaces,
+/// the comments and the case of the keywords aside. Return the first divergence otherwise
+pub(crate) fn check_tokens(
+    dialect: &dyn Dialect,
+    original: &str,
+    formatted: &str,
+) -> Result<(), FormatError> {
+    let original_tokens = Tokenizer::new(dialect, original).tokenize_with_location()?;
+    let formatted_tokens = Tokenizer::new(dialect, formatted)
+        .tokenize_with_location()
+        .map_err(|error| FormatError::SemanticChange {
+            path: None,
+            span: Span {
+                line: error.location.line,
+                column: error.location.column,
+            },
+            message: format!(
+                "The formatted script can't be tokenized : {}",
+                error.message
+            ),
+        })?;
+
+    let mut original_tokens = original_tokens
+        .iter()
+        .filter(|token| is_significant(&token.token));
+    let mut formatted_tokens = formatted_tokens
+        .iter()
+        .filter(|token| is_significant(&token.token));
+
+    let mut last_span = Span { line: 1, column: 1 };
+    loop {
+        let (span, message) = match (original_tokens.next(), formatted_tokens.next()) {
+            (None, None) => return Ok(()),
+            (Some(original), Some(formatted)) if same_token(&original.token, &formatted.token) => {
+                last_span = start_span(original);
+                continue;
+            }
+            (Some(original), Some(formatted)) => (
+                start_span(original),
+                format!(
+                    "The token '{}' is replaced by '{}' at {} of the formatted script",
+                    original.token,
+                    formatted.token,
+                    position(formatted)
+                ),
+            ),
+            (Some(original), None) => (
+                start_span(original),
+                format!(
+                    "The token '{}' is missing from the formatted script",
+                    original.token
+                ),
+            ),
+            (None, Some(formatted)) => (
+                last_span,
+                format!(
+                    "The token '{}' is added at {} of the formatted script",
+                    formatted.token,
+                    position(formatted)
+                ),
+            ),
+        };
+
+        return Err(FormatError::SemanticChange {
+            path: None,
+            span,
+            message,
+        });
+    }
+}
+
+/// Return true if both list of tokens are the same, the whitespaces, the comments and the case of
+/// the keywords aside
+pub(crate) fn same_tokens(original: &[Token], formatted: &[Token]) -> bool {
+    let original = original.iter().filter(|token| is_significant(token));
+    let mut formatted = formatted.iter().filter(|token| is_significant(token));
+
+    for token in original {
+        match formatted.next() {
+            Some(other) if same_token(token, other) => {}
+            _ => return false,
+        }
+    }
+    formatted.next().is_none()
+}
+
+fn same_token(original: &Token, formatted: &Token) -> bool {
+    match (original, formatted) {
+        (Token::Word(original), Token::Word(formatted))
+            if original.keyword != Keyword::NoKeyword
+                && original.quote_style.is_none()
+                && formatted.quote_style.is_none() =>
+        {
+            original.value.eq_ignore_ascii_case(&formatted.value)
+        }
+        _ => original == formatted,
+    }
+}
+
+fn start_span(token: &TokenWithSpan) -> Span {
+    Span {
+        line: token.span.start.line,
+        column: token.span.start.column,
+    }
+}
+
+fn position(token: &TokenWithSpan) -> String {
+    format!(
+        "line {} column {}",
+        token.span.start.line, token.span.start.column
+    )
+}
@@ -1,26 +1,204 @@
 use std::collections::HashSet;
+use std::fs::OpenOptions;
 use std::hash::Hash;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::{env, fs};
-use std::{fs::OpenOptions, path::PathBuf};
 
-use mylog::error;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::{FormatError, Span};
 
 const FOLDER_PATH: &str = "sqlformater";
 const SETTINGS_PATH: &str = "settings.json";
+/// Settings file written by `sqlformater init` without path, discovered from the current directory
+const TEMPLATE_PATH: &str = "sqlformater.toml";
+
+/// Comments of the settings in the template written by `sqlformater init`
+const TEMPLATE_COMMENTS: [(&str, &str); 25] = [
+    (
+        "database",
+        "Dialect of the scripts : \"generic\", \"postgre\", \"mysql\", \"mssql\", \"bigquery\", etc",
+    ),
+    (
+        "keywords_case",
+        "Case of the keywords : \"uppercase\" or \"lowercase\"",
+    ),
+    (
+        "tabulation_format",
+        "Indentation : \"tab\" or \"space\" followed by the number of characters",
+    ),
+    (
+        "linebreak_after_comma",
+        "Insert a line break after the commas of the constructs below",
+    ),
+    (
+        "linebreak_after_comma_in",
+        "Constructs where a line break is inserted after each comma, \"*\" for every construct",
+    ),
+    (
+        "linebreak_after_lparenthesis",
+        "Insert a line break after the parenthesis grouping an expression",
+    ),
+    (
+        "linebreak_after_lbrace",
+        "Insert a line break after each left brace",
+    ),
+    (
+        "linebreak_after_lbracket",
+        "Insert a line break after each left bracket",
+    ),
+    (
+        "linebreak_after_semicolon",
+        "Insert a line break after each semicolon",
+    ),
+    (
+        "linebreak_after_keywords",
+        "Keywords followed by a line break, case-insensitive",
+    ),
+    (
+        "linebreak_before_keywords",
+        "Keywords preceded by a line break, case-insensitive",
+    ),
+    (
+        "indentation_parenthesis",
+        "Indent the content of the parenthesis grouping an expression",
+    ),
+    ("indentation_braces", "Indent the content of the braces"),
+    ("indentation_brackets", "Indent the content of the brackets"),
+    (
+        "indentation_clauses",
+        "Start the clauses below on a new line and indent their content",
+    ),
+    (
+        "clauses",
+        "Clauses indented by indentation_clauses, case-insensitive",
+    ),
+    (
+        "parenthesis_subquery",
+        "Layout of the parenthesis of the subqueries",
+    ),
+    (
+        "parenthesis_function_call",
+        "Layout of the parenthesis of the function calls",
+    ),
+    (
+        "parenthesis_type_modifier",
+        "Layout of the parenthesis of the type modifiers",
+    ),
+    (
+        "parenthesis_in_list",
+        "Layout of the parenthesis of the lists of values",
+    ),
+    (
+        "parenthesis_column_definitions",
+        "Layout of the parenthesis of the column definitions",
+    ),
+    ("engine", "Formatting engine : \"tokens\" or \"ast\""),
+    (
+        "semantic_check",
+        "Refuse the formatted scripts whose tokens differ from the original scripts",
+    ),
+    (
+        "extensions",
+        "Extensions of the scripts found in the target folders",
+    ),
+    (
+        "overrides",
+        "Settings replaced for the scripts matching some globs, see the help of the settings",
+    ),
+];
+
+/// Configuration files discovered in the folder of the scripts and its parents, by priority
+const CONFIG_FILES: [&str; 5] = [
+    ".sqlformater.json",
+    "sqlformater.toml",
+    ".sqlformater.yaml",
+    ".sqlformater.yml",
+    "pyproject.toml",
+];
+
+/// Accepted values of the setting `database`, case-insensitive
+const DATABASES: [&str; 15] = [
+    "ansi",
+    "bigquery",
+    "clickhouse",
+    "databricks",
+    "duckdb",
+    "generic",
+    "hive",
+    "mssql",
+    "mysql",
+    "postgre",
+    "postgres",
+    "postgresql",
+    "redshift",
+    "snowflake",
+    "sqlite",
+];
+
+/// Accepted values of the setting `keywords_case`
+const KEYWORDS_CASES: [&str; 4] = ["uppercase", "upper", "lowercase", "lower"];
+
+/// Accepted values of the setting `engine`
+const ENGINES: [&str; 2] = ["tokens", "ast"];
+
+/// Default clauses indented by the setting `indentation_clauses`
+const CLAUSES: [&str; 27] = [
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "JOIN",
+    "INNER JOIN",
+    "LEFT JOIN",
+    "LEFT OUTER JOIN",
+    "RIGHT JOIN",
+    "RIGHT OUTER JOIN",
+    "FULL JOIN",
+    "FULL OUTER JOIN",
+    "CROSS JOIN",
+    "GROUP BY",
+    "HAVING",
+    "ORDER BY",
+    "LIMIT",
+    "OFFSET",
+    "WINDOW",
+    "QUALIFY",
+    "UNION",
+    "UNION ALL",
+    "INTERSECT",
+    "EXCEPT",
+    "RETURNING",
+    "SET",
+    "VALUES",
+    "ON CONFLICT",
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Settings {
-    /// Database name (Oracle, PostgreSQL, MySQL, etc)
+    /// Ignore the configuration files of the parent folders while discovering the settings
+    #[serde(skip_serializing)]
+    pub root: bool,
+    /// Database name (PostgreSQL, MySQL, etc)
+    #[serde(deserialize_with = "deserialize_database")]
     pub database: String,
     /// Case of each SQL Keyword
+    #[serde(deserialize_with = "deserialize_keywords_case")]
     pub keywords_case: String,
     /// Customize the tabulations
+    #[serde(deserialize_with = "deserialize_tabulation_format")]
     pub tabulation_format: String,
     /// Insert linebreak after each comma ','
     pub linebreak_after_comma: bool,
+    /// Constructs where a linebreak is inserted after each comma (select, column_definitions, etc)
+    #[serde(
+        serialize_with = "serialize_hashset",
+        deserialize_with = "deserialize_hashset"
+    )]
+    pub linebreak_after_comma_in: HashSet<String>,
     /// Insert linebreak after each left parenthesis '('
     pub linebreak_after_lparenthesis: bool,
     /// Insert linebreak after each left brace '{'
@@ -30,10 +208,16 @@ pub struct Settings {
     /// Insert linebreak after each semicolon
     pub linebreak_after_semicolon: bool,
     /// Insert linebreak after specifieds SQL Keywords
-    #[serde(deserialize_with = "deserialize_hashset")]
+    #[serde(
+        serialize_with = "serialize_hashset",
+        deserialize_with = "deserialize_hashset"
+    )]
     pub linebreak_after_keywords: HashSet<String>,
     /// Insert linebreak after specifieds SQL Keywords
-    #[serde(deserialize_with = "deserialize_hashset")]
+    #[serde(
+        serialize_with = "serialize_hashset",
+        deserialize_with = "deserialize_hashset"
+    )]
     pub linebreak_before_keywords: HashSet<String>,
     /// Insert indentations between the parenthesis
     pub indentation_parenthesis: bool,
@@ -43,10 +227,186 @@ pub struct Settings {
     pub indentation_brackets: bool,
     /// Insert indentations between clauses
     pub indentation_clauses: bool,
+    /// Clauses indented by the setting `indentation_clauses` (i.e. "SELECT", "GROUP BY")
+    #[serde(
+        serialize_with = "serialize_hashset",
+        deserialize_with = "deserialize_hashset"
+    )]
+    pub clauses: HashSet<String>,
+    /// Layout of the parenthesis of the subqueries '(SELECT ...)'
+    #[serde(deserialize_with = "deserialize_block_layout")]
+    pub parenthesis_subquery: ParenthesisLayout,
+    /// Layout of the parenthesis of the function calls 'COUNT(*)'
+    #[serde(deserialize_with = "deserialize_inline_layout")]
+    pub parenthesis_function_call: ParenthesisLayout,
+    /// Layout of the parenthesis of the type modifiers 'VARCHAR(20)'
+    #[serde(deserialize_with = "deserialize_inline_layout")]
+    pub parenthesis_type_modifier: ParenthesisLayout,
+    /// Layout of the parenthesis of the lists 'IN (1, 2)'
+    #[serde(deserialize_with = "deserialize_inline_layout")]
+    pub parenthesis_in_list: ParenthesisLayout,
+    /// Layout of the parenthesis of the column definitions 'CREATE TABLE t (...)'
+    #[serde(deserialize_with = "deserialize_block_layout")]
+    pub parenthesis_column_definitions: ParenthesisLayout,
+    /// Formatting engine : "tokens" or "ast" (fallback on "tokens" for unparsable statements)
+    #[serde(deserialize_with = "deserialize_engine")]
+    pub engine: String,
+    /// Refuse the formatted script if its tokens differ from the original script
+    pub semantic_check: bool,
+    /// Extensions of the scripts found in the target folders (i.e. "sql", "pgsql", "bq.sql")
+    pub extensions: Vec<String>,
+    /// Settings applied to the scripts matching some globs, in order
+    pub overrides: Vec<SettingsOverride>,
+}
+
+/// Settings replacing the others for the scripts matching some globs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsOverride {
+    /// Globs matched against the path of the scripts relative to the folder of the configuration
+    /// file, or against their file name
+    pub files: Vec<String>,
+    /// Settings replaced for the matching scripts (i.e. {"database": "postgre"})
+    pub settings: PartialSettings,
+}
+
+/// Settings of an override, the fields absent from the configuration file are kept
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PartialSettings {
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_database"
+    )]
+    pub database: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_keywords_case"
+    )]
+    pub keywords_case: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_tabulation_format"
+    )]
+    pub tabulation_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak_after_comma: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_hashset"
+    )]
+    pub linebreak_after_comma_in: Option<HashSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak_after_lparenthesis: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak_after_lbrace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak_after_lbracket: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak_after_semicolon: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_hashset"
+    )]
+    pub linebreak_after_keywords: Option<HashSet<String>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_hashset"
+    )]
+    pub linebreak_before_keywords: Option<HashSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indentation_parenthesis: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indentation_braces: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indentation_brackets: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indentation_clauses: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_hashset"
+    )]
+    pub clauses: Option<HashSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parenthesis_subquery: Option<PartialParenthesisLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parenthesis_function_call: Option<PartialParenthesisLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parenthesis_type_modifier: Option<PartialParenthesisLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parenthesis_in_list: Option<PartialParenthesisLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parenthesis_column_definitions: Option<PartialParenthesisLayout>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_engine"
+    )]
+    pub engine: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub semantic_check: Option<bool>,
+}
+
+/// Layout of a kind of parenthesis group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParenthesisLayout {
+    /// Insert linebreak after the left parenthesis and before the right parenthesis
+    pub linebreak: bool,
+    /// Insert indentations between the parenthesis
+    pub indentation: bool,
+}
+
+/// Layout of a kind of parenthesis group in a configuration file, the fields absent from the
+/// file are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PartialParenthesisLayout {
+    /// Insert linebreak after the left parenthesis and before the right parenthesis
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linebreak: Option<bool>,
+    /// Insert indentations between the parenthesis
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indentation: Option<bool>,
+}
+
+/// Default layout of the subqueries and the column definitions
+const BLOCK_LAYOUT: ParenthesisLayout = ParenthesisLayout {
+    linebreak: true,
+    indentation: true,
+};
+
+impl ParenthesisLayout {
+    /// Layout with the fields of the partial layout in input replaced
+    fn with(mut self, partial: PartialParenthesisLayout) -> ParenthesisLayout {
+        replace(&mut self.linebreak, partial.linebreak);
+        replace(&mut self.indentation, partial.indentation);
+        self
+    }
 }
 
 pub struct SavedSettings(pub Settings, pub String);
 
+/// Table `[tool.sqlformater]` of a `pyproject.toml`
+#[derive(Deserialize)]
+struct PyProject<T> {
+    tool: Option<PyProjectTools<T>>,
+}
+
+#[derive(Deserialize)]
+struct PyProjectTools<T> {
+    sqlformater: Option<T>,
+}
+
+/// Serialize the set sorted, so the settings files are stable
+fn serialize_hashset<S>(set: &HashSet<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut vec = set.iter().collect::<Vec<&String>>();
+    vec.sort();
+    vec.serialize(serializer)
+}
+
 fn deserialize_hashset<'de, D, T>(deserializer: D) -> Result<HashSet<T>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -56,128 +416,269 @@ where
     Ok(vec.into_iter().collect())
 }
 
+fn deserialize_database<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_checked(deserializer, check_database)
+}
+
+fn deserialize_keywords_case<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_checked(deserializer, |value| {
+        check_value("keywords_case", value, &KEYWORDS_CASES)
+    })
+}
+
+fn deserialize_tabulation_format<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_checked(deserializer, |value| {
+        parse_tabulation_format(value).map(|_| ())
+    })
+}
+
+fn deserialize_engine<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_checked(deserializer, |value| check_value("engine", value, &ENGINES))
+}
+
+fn deserialize_optional_hashset<'de, D, T>(deserializer: D) -> Result<Option<HashSet<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Eq + Hash,
+{
+    deserialize_hashset(deserializer).map(Some)
+}
+
+fn deserialize_optional_database<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_database(deserializer).map(Some)
+}
+
+fn deserialize_optional_keywords_case<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_keywords_case(deserializer).map(Some)
+}
+
+fn deserialize_optional_tabulation_format<'de, D>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_tabulation_format(deserializer).map(Some)
+}
+
+fn deserialize_optional_engine<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_engine(deserializer).map(Some)
+}
+
+/// Deserialize the layout of the subqueries or the column definitions, the absent fields keep their
+/// default value
+fn deserialize_block_layout<'de, D>(deserializer: D) -> Result<ParenthesisLayout, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    PartialParenthesisLayout::deserialize(deserializer).map(|partial| BLOCK_LAYOUT.with(partial))
+}
+
+/// Deserialize the layout of the other parenthesis, the absent fields keep their default value
+fn deserialize_inline_layout<'de, D>(deserializer: D) -> Result<ParenthesisLayout, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    PartialParenthesisLayout::deserialize(deserializer)
+        .map(|partial| ParenthesisLayout::default().with(partial))
+}
+
+/// Deserialize a string checked by the function in input, so the error is reported at the
+/// position of the value
+fn deserialize_checked<'de, D>(
+    deserializer: D,
+    check: impl Fn(&str) -> Result<(), String>,
+) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    check(&value).map_err(serde::de::Error::custom)?;
+    Ok(value)
+}
+
 impl Default for Settings {
     fn default() -> Settings {
-        let mut hash_after: HashSet<String> = HashSet::new();
-        hash_after.insert("SELECT".to_string());
-        hash_after.insert("FROM".to_string());
-        hash_after.insert("WHERE".to_string());
+        let clauses: HashSet<String> = CLAUSES.iter().map(|c| c.to_string()).collect();
+
+        let comma_in: HashSet<String> = ["select", "column_definitions", "insert_columns"]
+            .into_iter()
+            .map(String::from)
+            .collect();
 
         Settings {
+            root: false,
             database: "generic".to_string(),
             keywords_case: "uppercase".to_string(),
             tabulation_format: "tab1".to_string(),
             linebreak_after_comma: true,
+            linebreak_after_comma_in: comma_in,
             linebreak_after_lparenthesis: true,
             linebreak_after_lbrace: true,
             linebreak_after_lbracket: false,
             linebreak_after_semicolon: true,
-            linebreak_after_keywords: hash_after,
+            linebreak_after_keywords: clauses.clone(),
             linebreak_before_keywords: HashSet::new(),
             indentation_parenthesis: true,
             indentation_braces: true,
             indentation_brackets: false,
             indentation_clauses: true,
+            clauses,
+            parenthesis_subquery: BLOCK_LAYOUT,
+            parenthesis_function_call: ParenthesisLayout::default(),
+            parenthesis_type_modifier: ParenthesisLayout::default(),
+            parenthesis_in_list: ParenthesisLayout::default(),
+            parenthesis_column_definitions: BLOCK_LAYOUT,
+            engine: "tokens".to_string(),
+            semantic_check: true,
+            extensions: vec!["sql".to_string()],
+            overrides: Vec::new(),
         }
     }
 }
 
-impl SavedSettings {
-    pub fn main(path: Option<PathBuf>) -> Self {
-        match &path {
-            Some(path) => {
-                if path.is_file() {
-                    match SavedSettings::from(path.to_path_buf()) {
-                        Ok(savedsettings) => savedsettings,
-                        Err(_) => {
-                            error!(
-                                "Errors detected while try to load the settings file : {:?}",
-                                path
-                            );
-                            SavedSettings(Settings::default(), format!("Invalid : {:?}", path))
-                        }
-                    }
-                } else {
-                    match SavedSettings::init(Some(path.to_path_buf())) {
-                        Ok(savedsettings) => savedsettings,
-                        Err(_) => {
-                            error!(
-                                "Errors detected while try to init the settings folder : {:?}",
-                                path
-                            );
-                            SavedSettings(Settings::default(), format!("Invalid : {:?}", path))
-                        }
-                    }
-                }
-            }
-            None => match SavedSettings::init(None) {
-                Ok(savedsettings) => savedsettings,
-                Err(_) => {
-                    let current_dir = env::current_dir().unwrap_or_default();
-                    error!(
-                        "Errors detected while try to init the settings folder : {:?}",
-                        current_dir
-                    );
-                    SavedSettings(Settings::default(), format!("Invalid : {:?}", current_dir))
-                }
-            },
+impl Settings {
+    /// Settings with the fields of the override in input replaced
+    pub(crate) fn with_override(&self, settings_override: &SettingsOverride) -> Settings {
+        let replaced = settings_override.settings.clone();
+        let mut settings = self.clone();
+        replace(&mut settings.database, replaced.database);
+        replace(&mut settings.keywords_case, replaced.keywords_case);
+        replace(&mut settings.tabulation_format, replaced.tabulation_format);
+        replace(
+            &mut settings.linebreak_after_comma,
+            replaced.linebreak_after_comma,
+        );
+        replace(
+            &mut settings.linebreak_after_comma_in,
+            replaced.linebreak_after_comma_in,
+        );
+        replace(
+            &mut settings.linebreak_after_lparenthesis,
+            replaced.linebreak_after_lparenthesis,
+        );
+        replace(
+            &mut settings.linebreak_after_lbrace,
+            replaced.linebreak_after_lbrace,
+        );
+        replace(
+            &mut settings.linebreak_after_lbracket,
+            replaced.linebreak_after_lbracket,
+        );
+        replace(
+            &mut settings.linebreak_after_semicolon,
+            replaced.linebreak_after_semicolon,
+        );
+        replace(
+            &mut settings.linebreak_after_keywords,
+            replaced.linebreak_after_keywords,
+        );
+        replace(
+            &mut settings.linebreak_before_keywords,
+            replaced.linebreak_before_keywords,
+        );
+        replace(
+            &mut settings.indentation_parenthesis,
+            replaced.indentation_parenthesis,
+        );
+        replace(
+            &mut settings.indentation_braces,
+            replaced.indentation_braces,
+        );
+        replace(
+            &mut settings.indentation_brackets,
+            replaced.indentation_brackets,
+        );
+        replace(
+            &mut settings.indentation_clauses,
+            replaced.indentation_clauses,
+        );
+        replace(&mut settings.clauses, replaced.clauses);
+        if let Some(partial) = replaced.parenthesis_subquery {
+            settings.parenthesis_subquery = settings.parenthesis_subquery.with(partial);
+        }
+        if let Some(partial) = replaced.parenthesis_function_call {
+            settings.parenthesis_function_call = settings.parenthesis_function_call.with(partial);
+        }
+        if let Some(partial) = replaced.parenthesis_type_modifier {
+            settings.parenthesis_type_modifier = settings.parenthesis_type_modifier.with(partial);
+        }
+        if let Some(partial) = replaced.parenthesis_in_list {
+            settings.parenthesis_in_list = settings.parenthesis_in_list.with(partial);
         }
+        if let Some(partial) = replaced.parenthesis_column_definitions {
+            settings.parenthesis_column_definitions =
+                settings.parenthesis_column_definitions.with(partial);
+        }
+        replace(&mut settings.engine, replaced.engine);
+        replace(&mut settings.semantic_check, replaced.semantic_check);
+        settings
     }
 
-    fn init(target_path: Option<PathBuf>) -> Result<Self, ()> {
-        let path: PathBuf;
-        match target_path {
-            Some(folder_path) => {
-                if !fs::exists(&folder_path).unwrap_or(false) {
-                    fs::create_dir_all(&folder_path).map_err(|e| {
-                        error!(
-                            "Failed to create the directory : {:?}\n\t{}",
-                            folder_path, e
-                        )
-                    })?;
-                }
-                path = folder_path;
-            }
-            None => {
-                path = env::current_dir()
-                    .map_err(|e| error!("{}", e))?
-                    .join(FOLDER_PATH);
-            }
+    /// Insert the setting `clauses` into the setting `linebreak_after_keywords` if
+    /// `indentation_clauses` is true. The keywords are matched case-insensitively
+    pub(crate) fn insert_clause_keywords(&mut self) {
+        if self.indentation_clauses {
+            let clauses = self.clauses.clone();
+            self.linebreak_after_keywords.extend(clauses);
         }
+    }
+}
 
-        let write_files = |input_path: &PathBuf| -> Result<SavedSettings, ()> {
-            let setting_path = input_path.join("settings.json");
-
-            if !fs::exists(&setting_path).unwrap_or(false) {
-                let mut settings = Settings::default();
-                let settings_path =
-                    write_settings(input_path, &settings).map_err(|e| error!("{}", e))?;
-                settings.tabulation_format = parse_tabulation_format(settings.tabulation_format);
-                Ok(SavedSettings(settings, settings_path))
-            } else {
-                match SavedSettings::from(setting_path) {
-                    Ok(savedsettings) => Ok(savedsettings),
-                    Err(_) => Err(()),
-                }
-            }
-        };
+/// Replace the setting by the value of the override, if any
+fn replace<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
 
-        if !fs::exists(&path).unwrap_or(false) {
-            match fs::create_dir(&path) {
-                Ok(_) => write_files(&path),
-                Err(error) => {
-                    error!("{}", error);
-                    Err(())
+impl SavedSettings {
+    /// Load the settings from the path in input, or from `./sqlformater/settings.json` if it
+    /// exists, the default settings otherwise. No file is written
+    pub fn main(path: Option<PathBuf>) -> Result<Self, FormatError> {
+        match path {
+            Some(path) => SavedSettings::from(path),
+            None => {
+                let path = env::current_dir()
+                    .unwrap_or_default()
+                    .join(FOLDER_PATH)
+                    .join(SETTINGS_PATH);
+                if path.is_file() {
+                    SavedSettings::from(path)
+                } else {
+                    Ok(SavedSettings(
+                        Settings::default(),
+                        "Default settings".to_string(),
+                    ))
                 }
             }
-        } else {
-            write_files(&path)
         }
     }
 
     /// This function extract the settings from the path in input, it could be the path of the 'settings.json'
     /// or a folder who's contains a file 'settings.json'
-    fn from(path: PathBuf) -> Result<Self, ()> {
+    fn from(path: PathBuf) -> Result<Self, FormatError> {
         let mut settings_path = path;
         if settings_path.is_dir() {
             settings_path.push(SETTINGS_PATH)
@@ -186,128 +687,390 @@ impl SavedSettings {
         let mut file = OpenOptions::new()
             .read(true)
             .open(&settings_path)
-            .map_err(|e| error!("{}", e))?;
+            .map_err(|e| FormatError::io(&settings_path, e))?;
 
         let mut content = String::new();
         let _ = file
             .read_to_string(&mut content)
-            .map_err(|e| error!("{}", e))?;
-
-        let mut settings: Settings = serde_json::from_str(&content).map_err(|e| error!("{}", e))?;
+            .map_err(|e| FormatError::io(&settings_path, e))?;
 
-        settings.tabulation_format = parse_tabulation_format(settings.tabulation_format);
+        let settings: Settings =
+            deserialize(&settings_path, &content)?.ok_or_else(|| FormatError::InvalidSettings {
+                path: Some(settings_path.clone()),
+                span: None,
+                message: "Missing table [tool.sqlformater]".to_string(),
+            })?;
 
         SavedSettings::update(settings, settings_path)
     }
 
-    fn update(mut settings: Settings, path: PathBuf) -> Result<Self, ()> {
-        match (
-            settings.indentation_clauses,
-            settings.keywords_case.as_str(),
-        ) {
-            (true, "lowercase" | "lower") => {
-                if settings
-                    .linebreak_after_keywords
-                    .insert("select".to_string())
-                    || settings.linebreak_after_keywords.insert("from".to_string())
-                    || settings
-                        .linebreak_after_keywords
-                        .insert("where".to_string())
-                {
-                    if let Err(error) = write_settings(&mut path.to_path_buf(), &settings) {
-                        error!("{} - settings path : {}", error, path.display());
-                        return Err(());
-                    }
-                }
-                Ok(SavedSettings(settings, path.display().to_string()))
-            }
-            (true, "uppercase" | "upper") => {
-                if settings
-                    .linebreak_after_keywords
-                    .insert("SELECT".to_string())
-                    || settings.linebreak_after_keywords.insert("FROM".to_string())
-                    || settings
-                        .linebreak_after_keywords
-                        .insert("WHERE".to_string())
-                {
-                    if let Err(error) = write_settings(&mut path.to_path_buf(), &settings) {
-                        error!("{} - settings path : {}", error, path.display());
-                        return Err(());
-                    }
-                }
+    fn update(settings: Settings, path: PathBuf) -> Result<Self, FormatError> {
+        match settings.keywords_case.as_str() {
+            "lowercase" | "lower" | "uppercase" | "upper" => {
                 Ok(SavedSettings(settings, path.display().to_string()))
             }
-            (false, "lowercase" | "lower" | "uppercase" | "upper") => {
-                Ok(SavedSettings(settings, path.display().to_string()))
+            unsupported_case => Err(FormatError::UnsupportedCase {
+                path: Some(path.clone()),
+                case: unsupported_case.to_string(),
+            }),
+        }
+    }
+}
+
+/// Settings of the configuration files found in the folder in input and its parents, up to a file
+/// holding `"root": true`. The fields of the nearest files replace the fields of the farthest ones.
+/// Return the settings with the folder of the file declaring the setting `overrides`, `None`
+/// without configuration file
+pub(crate) fn discover_settings(folder: &Path) -> Result<Option<(Settings, PathBuf)>, FormatError> {
+    let mut files = Vec::new();
+    for folder in folder.ancestors() {
+        let mut fields = None;
+        for path in CONFIG_FILES.iter().map(|name| folder.join(name)) {
+            if path.is_file() {
+                fields = read_fields(&path)?;
             }
-            (_, unsupported_case) => {
-                error!("Unsupported case : '{}'", unsupported_case);
-                Err(())
+            if fields.is_some() {
+                break;
             }
         }
+        let Some(fields) = fields else {
+            continue;
+        };
+
+        let root = fields.get("root") == Some(&Value::Bool(true));
+        files.push((folder.to_path_buf(), fields));
+        if root {
+            break;
+        }
     }
+
+    let Some((mut overrides_root, _)) = files.first().cloned() else {
+        return Ok(None);
+    };
+    let mut merged = Map::new();
+    for (folder, mut fields) in files.into_iter().rev() {
+        fields.remove("root");
+        if fields.contains_key("overrides") {
+            overrides_root = folder;
+        }
+        merged.extend(fields);
+    }
+
+    let settings: Settings = serde_json::from_value(Value::Object(merged))?;
+    Ok(Some((settings, overrides_root)))
 }
 
-fn parse_tabulation_format(tabulation_format: String) -> String {
-    let result = |pattern: &str, number: String| -> String {
-        if let Ok(number) = number.parse::<usize>() {
-            pattern.repeat(number)
-        } else {
-            pattern.to_string()
+/// Fields of the configuration file in input, checked against the settings. `None` for a
+/// `pyproject.toml` without table `[tool.sqlformater]`
+fn read_fields(path: &Path) -> Result<Option<Map<String, Value>>, FormatError> {
+    let content = fs::read_to_string(path).map_err(|e| FormatError::io(path, e))?;
+
+    deserialize::<Settings>(path, &content)?;
+    deserialize(path, &content)
+}
+
+/// Deserialize the settings file in input from the format of its name : JSON, TOML, YAML or the
+/// table `[tool.sqlformater]` of a `pyproject.toml` (`None` without this table)
+fn deserialize<T: DeserializeOwned>(path: &Path, content: &str) -> Result<Option<T>, FormatError> {
+    let name = path.file_name().and_then(|name| name.to_str());
+    let extension = path.extension().and_then(|extension| extension.to_str());
+
+    let result = match (name, extension) {
+        (Some("pyproject.toml"), _) => toml::from_str::<PyProject<T>>(content)
+            .map(|pyproject| pyproject.tool.and_then(|tool| tool.sqlformater))
+            .map_err(|e| toml_error(content, e)),
+        (_, Some("toml")) => toml::from_str(content)
+            .map(Some)
+            .map_err(|e| toml_error(content, e)),
+        (_, Some("yaml" | "yml")) => serde_yaml::from_str(content)
+            .map(Some)
+            .map_err(FormatError::from),
+        _ => serde_json::from_str(content)
+            .map(Some)
+            .map_err(FormatError::from),
+    };
+    result.map_err(|mut e| {
+        if let FormatError::InvalidSettings { message, .. } = &mut e {
+            *message = with_suggestion(message);
         }
+        e.with_path(path)
+    })
+}
+
+/// Replace the accepted fields listed in the message of an unknown field by the closest one
+fn with_suggestion(message: &str) -> String {
+    let Some(field) = message
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next())
+    else {
+        return message.to_string();
+    };
+    let Some((_, expected)) = message.split_once(", expected ") else {
+        return message.to_string();
     };
 
-    if tabulation_format.starts_with("tab") {
-        result("\t", tabulation_format.replace("tab", ""))
-    } else if tabulation_format.starts_with("space") {
-        result(" ", tabulation_format.replace("space", ""))
-    } else {
-        error!("Unsupported tabulation format : {}", tabulation_format);
-        "\t".to_string()
+    // The accepted fields are quoted with backticks
+    let fields = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .collect::<Vec<&str>>();
+    match closest(field, &fields) {
+        Some(closest) => format!("unknown field `{}`, did you mean `{}`?", field, closest),
+        None => message.to_string(),
+    }
+}
+
+/// Check the setting `database`, case-insensitive
+pub(crate) fn check_database(database: &str) -> Result<(), String> {
+    let lowercase = database.to_lowercase();
+    if DATABASES.contains(&lowercase.as_str()) {
+        return Ok(());
+    }
+    Err(unsupported(
+        "database",
+        database,
+        closest(&lowercase, &DATABASES),
+        &DATABASES,
+    ))
+}
+
+/// Check the value of a setting against its accepted values, the message suggests the closest one
+pub(crate) fn check_value(key: &str, value: &str, accepted: &[&str]) -> Result<(), String> {
+    if accepted.contains(&value) {
+        return Ok(());
     }
+
+    Err(unsupported(key, value, closest(value, accepted), accepted))
+}
+
+fn unsupported(key: &str, value: &str, closest: Option<&str>, accepted: &[&str]) -> String {
+    let message = format!("unsupported {} `{}`", key, value);
+    match closest {
+        Some(closest) => format!("{}, did you mean `{}`?", message, closest),
+        None => format!("{}, expected one of `{}`", message, accepted.join("`, `")),
+    }
+}
+
+/// Candidate the nearest to the value in input, if it's close enough to be a typo
+fn closest<'a>(value: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|candidate| (distance(value, candidate), *candidate))
+        .filter(|(distance, _)| distance * 3 <= value.chars().count().max(3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
 }
 
-pub fn write_gitignore(path: &PathBuf) -> Result<(), String> {
-    let target_path: PathBuf;
-    if path.is_dir() {
+/// Levenshtein distance between the strings in input
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn toml_error(content: &str, error: toml::de::Error) -> FormatError {
+    let span = error.span().map(|span| {
+        let before = &content[..span.start.min(content.len())];
+        Span {
+            line: before.matches('\n').count() as u64 + 1,
+            column: before
+                .rsplit('\n')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .count() as u64
+                + 1,
+        }
+    });
+
+    FormatError::InvalidSettings {
+        path: None,
+        span,
+        message: error.message().to_string(),
+    }
+}
+
+/// Convert the setting `tabulation_format` (i.e. "tab1", "space4") into the indentation string
+pub(crate) fn parse_tabulation_format(tabulation_format: &str) -> Result<String, String> {
+    let kind = tabulation_format.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = &tabulation_format[kind.len()..];
+    let pattern = match kind {
+        "tab" => "\t",
+        "space" => " ",
+        _ => {
+            let message = format!("unsupported tabulation_format `{}`", tabulation_format);
+            return Err(match closest(kind, &["tab", "space"]) {
+                Some(closest) => format!(
+                    "{}, did you mean `{}{}`?",
+                    message,
+                    closest,
+                    if number.is_empty() { "1" } else { number }
+                ),
+                None => format!(
+                    "{}, expected `tab` or `space` followed by a number (i.e. `tab1`, `space4`)",
+                    message
+                ),
+            });
+        }
+    };
+
+    Ok(match number.parse::<usize>() {
+        Ok(number) => pattern.repeat(number),
+        Err(_) => pattern.to_string(),
+    })
+}
+
+pub fn write_gitignore(path: &Path) -> Result<(), FormatError> {
+    let target_path: PathBuf = if path.is_dir() {
         if !fs::exists(path).unwrap_or(false) {
-            fs::create_dir_all(path).map_err(|e| format!("{}", e))?;
+            fs::create_dir_all(path).map_err(|e| FormatError::io(path, e))?;
         }
-        target_path = path.join(".gitignore");
+        path.join(".gitignore")
     } else {
-        target_path = path.clone();
-    }
+        path.to_path_buf()
+    };
 
     let mut file = OpenOptions::new()
         .create(true)
         .truncate(true)
         .write(true)
         .open(&target_path)
-        .map_err(|e| format!("{} when try to create/open the file {:?}", e, path))?;
+        .map_err(|e| FormatError::io(&target_path, e))?;
 
     let _ = file.write_all(b"*");
     Ok(())
 }
 
-fn write_settings(path: &PathBuf, settings: &Settings) -> Result<String, String> {
-    let target_path: PathBuf;
-    if path.is_dir() {
-        target_path = path.join(SETTINGS_PATH);
-    } else {
-        target_path = path.clone();
+/// Write a commented template of the default settings at the path in input : a TOML, YAML or
+/// JSON file, a `pyproject.toml` (the table `[tool.sqlformater]` is appended) or a folder (its
+/// `settings.json`). Without path, the template is written in `./sqlformater.toml`. An existing
+/// settings file is never replaced
+pub(crate) fn write_template(path: Option<&Path>) -> Result<PathBuf, FormatError> {
+    let path = match path {
+        Some(path) if path.is_dir() || path.extension().is_none() => path.join(SETTINGS_PATH),
+        Some(path) => path.to_path_buf(),
+        None => env::current_dir()
+            .map_err(|e| FormatError::io(Path::new(TEMPLATE_PATH), e))?
+            .join(TEMPLATE_PATH),
+    };
+    let already_exists = || {
+        FormatError::io(
+            &path,
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "The settings file already exists",
+            ),
+        )
+    };
+
+    let name = path.file_name().and_then(|name| name.to_str());
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let mut content = String::new();
+    if name == Some("pyproject.toml") && path.is_file() {
+        content = fs::read_to_string(&path).map_err(|e| FormatError::io(&path, e))?;
+        if deserialize::<Map<String, Value>>(&path, &content)?.is_some() {
+            return Err(already_exists());
+        }
+        if !content.is_empty() {
+            content.push_str(if content.ends_with('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+    } else if path.exists() {
+        return Err(already_exists());
     }
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&target_path)
-        .map_err(|e| format!("{}", e))?;
+    let settings = Settings::default();
+    match (name, extension) {
+        (Some("pyproject.toml"), _) => {
+            content.push_str("[tool.sqlformater]\n");
+            content.push_str(&toml_template(&settings)?);
+        }
+        (_, Some("toml")) => content.push_str(&toml_template(&settings)?),
+        (_, Some("yaml" | "yml")) => content.push_str(&yaml_template(&settings)?),
+        _ => {
+            content.push_str(
+                &serde_json::to_string_pretty(&settings)
+                    .map_err(|e| FormatError::from(e).with_path(&path))?,
+            );
+            content.push('\n');
+        }
+    }
 
-    let json_object = serde_json::to_string(settings).map_err(|e| format!("{}", e))?;
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).map_err(|e| FormatError::io(folder, e))?;
+    }
+    fs::write(&path, content).map_err(|e| FormatError::io(&path, e))?;
+    Ok(path)
+}
+
+fn toml_template(settings: &Settings) -> Result<String, FormatError> {
+    let mut template = String::from(
+        "# Settings of sqlformater, see `sqlformater --help-settings`\n\
+         # Add `root = true` to ignore the settings files of the parent folders\n",
+    );
+    for (key, value) in template_fields(settings)? {
+        let value = match &value {
+            // One item by line
+            Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_string) => {
+                let items = items
+                    .iter()
+                    .map(|item| format!("    {},\n", item))
+                    .collect::<String>();
+                format!("[\n{}]", items)
+            }
+            value => toml::Value::try_from(value)
+                .map_err(|e| FormatError::InvalidSettings {
+                    path: None,
+                    span: None,
+                    message: e.to_string(),
+                })?
+                .to_string(),
+        };
+        template.push_str(&format!("\n# {}\n{} = {}\n", comment(key), key, value));
+    }
+    Ok(template)
+}
+
+fn yaml_template(settings: &Settings) -> Result<String, FormatError> {
+    let mut template = String::from(
+        "# Settings of sqlformater, see `sqlformater --help-settings`\n\
+         # Add `root: true` to ignore the settings files of the parent folders\n",
+    );
+    for (key, value) in template_fields(settings)? {
+        let mut field = Map::new();
+        field.insert(key.to_string(), value);
+        let field = serde_yaml::to_string(&field).map_err(FormatError::from)?;
+        template.push_str(&format!("\n# {}\n{}", comment(key), field));
+    }
+    Ok(template)
+}
 
-    file.write_all(json_object.as_bytes())
-        .map_err(|e| format!("{}", e))?;
+/// Fields of the settings in the order of the template
+fn template_fields(settings: &Settings) -> Result<Vec<(&'static str, Value)>, FormatError> {
+    let Value::Object(mut fields) = serde_json::to_value(settings)? else {
+        return Ok(Vec::new());
+    };
+    Ok(TEMPLATE_COMMENTS
+        .iter()
+        .filter_map(|(key, _)| Some((*key, fields.remove(*key)?)))
+        .collect())
+}
 
-    Ok(target_path.display().to_string())
+fn comment(key: &str) -> &'static str {
+    TEMPLATE_COMMENTS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map_or("", |(_, comment)| comment)
 }
@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use mylog::error;
+
+use crate::error::FormatError;
+use crate::resolver::Resolver;
+
+/// File with gitignore syntax listing the paths skipped while looking for scripts
+pub(crate) const IGNORE_FILE: &str = ".sqlformaterignore";
+
+/// Selection of the scripts inside the target folders, from the `--include` and `--exclude` globs
+/// matched against the paths relative to these folders
+#[derive(Debug, Clone)]
+pub(crate) struct ScriptFilter<'a> {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// Settings of the scripts, for the setting `extensions` of their own folder
+    resolver: &'a Resolver,
+}
+
+impl<'a> ScriptFilter<'a> {
+    pub(crate) fn new(
+        include: &[String],
+        exclude: &[String],
+        resolver: &'a Resolver,
+    ) -> Result<Self, FormatError> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(include)?)
+        };
+        Ok(ScriptFilter {
+            include,
+            exclude: glob_set(exclude)?,
+            resolver,
+        })
+    }
+
+    /// Return true if the script at the path in input, inside the folder in input, is selected
+    pub(crate) fn is_selected(&self, folder: &Path, path: &Path) -> Result<bool, FormatError> {
+        let relative = path.strip_prefix(folder).unwrap_or(path);
+        if !self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative))
+            || self.exclude.is_match(relative)
+        {
+            return Ok(false);
+        }
+        self.resolver.is_script(path)
+    }
+
+    /// Scripts inside the folder in input, without the paths ignored by the `.gitignore` and
+    /// `.sqlformaterignore` files. The symbolic links are followed once
+    pub(crate) fn scripts(&self, folder: &Path) -> Result<Vec<PathBuf>, FormatError> {
+        self.walk(folder, None)
+    }
+
+    /// Return true if the script at the path in input is one of the scripts of the folder in
+    /// input, only the folders containing the script are walked
+    pub(crate) fn contains(&self, folder: &Path, path: &Path) -> Result<bool, FormatError> {
+        Ok(self
+            .walk(folder, Some(path))?
+            .iter()
+            .any(|script| script == path))
+    }
+
+    /// Scripts inside the folder in input, or only the ones on the way to the path in input
+    fn walk(&self, folder: &Path, target: Option<&Path>) -> Result<Vec<PathBuf>, FormatError> {
+        let root = folder.to_path_buf();
+        let target = target.map(Path::to_path_buf);
+        let exclude = self.exclude.clone();
+        let walk = WalkBuilder::new(folder)
+            .hidden(false)
+            .follow_links(true)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .filter_entry(move |entry| {
+                let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                entry.file_name() != ".git"
+                    && !exclude.is_match(relative)
+                    && target
+                        .as_ref()
+                        .is_none_or(|target| target.starts_with(entry.path()))
+            })
+            .build();
+
+        let mut visited = HashSet::new();
+        let mut scripts = Vec::new();
+        for entry in walk {
+            match entry {
+                Ok(entry) => {
+                    let path = entry.path();
+                    if entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_file())
+                        && self.is_selected(folder, path)?
+                        && visited.insert(path.canonicalize().unwrap_or(path.to_path_buf()))
+                    {
+                        scripts.push(entry.into_path());
+                    }
+                }
+                // Symbolic link loops and unreadable folders are skipped
+                Err(e) => error!("{}", e),
+            }
+        }
+        Ok(scripts)
+    }
+}
+
+/// Return true if the file name of the path in input ends with one of the extensions in input
+/// (i.e. "sql", "pgsql", "bq.sql")
+pub(crate) fn is_script(path: &Path, extensions: &[String]) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    extensions.iter().any(|extension| {
+        name.strip_suffix(extension.trim_start_matches('.'))
+            .and_then(|name| name.strip_suffix('.'))
+            .is_some_and(|name| !name.is_empty())
+    })
+}
+
+pub(crate) fn glob_set(globs: &[String]) -> Result<GlobSet, FormatError> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|e| FormatError::InvalidSettings {
+            path: None,
+            span: None,
+            message: format!("Invalid glob '{}' : {}", glob, e),
+        })?);
+    }
+    builder.build().map_err(|e| FormatError::InvalidSettings {
+        path: None,
+        span: None,
+        message: e.to_string(),
+    })
+}
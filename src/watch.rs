@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use mylog::error;
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::{self, RecursiveMode};
+
+use crate::error::FormatError;
+use crate::formater::{Formatter, format_script, read_script};
+use crate::resolver::Resolver;
+use crate::walk::ScriptFilter;
+
+/// Time without any event on a file before it's formatted
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Format the scripts created or modified inside the folders in input and selected by the filter
+/// (without the ignored paths, as the scripts formatted before watching), or matching the files
+/// in input, until the process is stopped
+pub(crate) fn watch(
+    resolver: &Resolver,
+    filter: &ScriptFilter,
+    folders: &[PathBuf],
+    files: &[PathBuf],
+) -> Result<(), FormatError> {
+    let canonicalize = |path: &PathBuf| path.canonicalize().map_err(|e| FormatError::io(path, e));
+    let folders = folders
+        .iter()
+        .map(canonicalize)
+        .collect::<Result<Vec<PathBuf>, FormatError>>()?;
+    let files = files
+        .iter()
+        .map(canonicalize)
+        .collect::<Result<Vec<PathBuf>, FormatError>>()?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, sender).map_err(|e| watch_error(None, e))?;
+    for folder in &folders {
+        debouncer
+            .watcher()
+            .watch(folder, RecursiveMode::Recursive)
+            .map_err(|e| watch_error(Some(folder), e))?;
+    }
+    // The parent folders are watched as editors often save a file by replacing it
+    for folder in files.iter().filter_map(|file| file.parent()) {
+        debouncer
+            .watcher()
+            .watch(folder, RecursiveMode::NonRecursive)
+            .map_err(|e| watch_error(Some(folder), e))?;
+    }
+    println!("\nWatching the scripts, press Ctrl+C to stop");
+
+    // Content of the scripts written by the formatter, so their own events are ignored
+    let mut written: HashMap<PathBuf, String> = HashMap::new();
+    for events in receiver {
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                error!("{}", watch_error(None, e));
+                continue;
+            }
+        };
+
+        for event in events {
+            let Ok(path) = event.path.canonicalize() else {
+                continue;
+            };
+            let watched = if files.contains(&path) {
+                Ok(true)
+            } else {
+                is_watched(filter, &folders, &path)
+            };
+            if !path.is_file() || watched.as_ref().is_ok_and(|watched| !watched) {
+                continue;
+            }
+
+            match watched
+                .and_then(|_| resolver.formatter(&path))
+                .and_then(|formatter| format_file(&formatter, &path, &mut written))
+            {
+                Ok(true) => println!("\nSuccessfully format the file : {}", path.display()),
+                Ok(false) => {}
+                Err(error) => {
+                    error!("{}", error);
+                    eprintln!("ERROR : {}", error);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return true if the script at the path in input is one of the scripts of the folders in input
+fn is_watched(
+    filter: &ScriptFilter,
+    folders: &[PathBuf],
+    path: &Path,
+) -> Result<bool, FormatError> {
+    for folder in folders.iter().filter(|folder| path.starts_with(folder)) {
+        if filter.contains(folder, path)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Format the script unless its content is the one written by the formatter, return true if the
+/// script is written
+fn format_file(
+    formatter: &Formatter,
+    path: &Path,
+    written: &mut HashMap<PathBuf, String>,
+) -> Result<bool, FormatError> {
+    let script = read_script(path)?;
+    if written.get(path) == Some(&script) {
+        return Ok(false);
+    }
+
+    let formated_script = format_script(formatter, &script, &[]).map_err(|e| e.with_path(path))?;
+    let changed = formated_script != script;
+    if changed {
+        fs::write(path, &formated_script).map_err(|e| FormatError::io(path, e))?;
+    }
+    written.insert(path.to_path_buf(), formated_script);
+    Ok(changed)
+}
+
+fn watch_error(path: Option<&Path>, error: notify::Error) -> FormatError {
+    FormatError::Io {
+        path: path.map(Path::to_path_buf),
+        source: Arc::new(io::Error::other(error.to_string())),
+    }
+}
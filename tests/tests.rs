@@ -1,6 +1,9 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
 use std::{env, fs};
 
-use sqlformater::cli;
+use sqlformater::{FormatError, Formatter, ParenthesisLayout, Settings, Span, cli, format_str};
 
 const RESULTS_FOLDER: &str = "tests_results";
 
@@ -18,13 +21,90 @@ fn test_cli(args: Vec<&str>, iteration: usize) {
     cli::main(args);
 }
 
+/// Temporary folder of a test, unique to the run and removed at the end of the test
+struct TestFolder(PathBuf);
+
+impl TestFolder {
+    fn new(name: &str) -> TestFolder {
+        let path = env::temp_dir().join(format!("sqlformater_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestFolder(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Write the file, with its parent folders
+    fn write(&self, path: &str, content: &str) {
+        let path = self.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.join(path)).unwrap()
+    }
+
+    /// Command running sqlformater in the folder, with its logs kept in the folder
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_sqlformater"));
+        command
+            .args(args)
+            .arg(format!("--logs_path={}", self.join(".logs").display()))
+            .current_dir(&self.0);
+        command
+    }
+
+    fn sqlformater(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn sqlformater_stdin(&self, args: &[&str], stdin: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Git command in the folder, the hooks installed running this sqlformater
+    fn git(&self, args: &[&str]) -> Output {
+        Command::new("git")
+            .args(args)
+            .env("SQLFORMATER", env!("CARGO_BIN_EXE_sqlformater"))
+            .current_dir(&self.0)
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for TestFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_initialization() {
     for index in 0..5 {
         test_cli(vec!["*"], index);
 
         assert!(fs::exists("sqlformater").unwrap_or(false));
-        assert!(fs::exists("sqlformater/settings.json").unwrap_or(false));
         assert!(fs::exists("sqlformater/.gitignore").unwrap_or(false));
     }
 }
@@ -42,10 +122,1272 @@ fn test_logs_path() {
 #[test]
 fn test_settings_path() {
     for index in 0..5 {
-        test_cli(vec!["-settings_path=path_to_customized_settings"], index);
+        test_cli(
+            vec!["init", "-settings_path=path_to_customized_settings"],
+            index,
+        );
 
         assert!(fs::exists("path_to_customized_settings").unwrap_or(false));
         assert!(fs::exists("path_to_customized_settings/settings.json").unwrap_or(false));
-        assert!(fs::exists("path_to_customized_settings/.gitignore").unwrap_or(false));
     }
 }
+
+#[test]
+fn test_format_str() {
+    let settings = Settings::default();
+    let formatted = format_str(&settings, "select a, b from t where a = 1;").unwrap();
+    let formatted = formatted.lines().map(str::trim_end).collect::<Vec<&str>>();
+
+    assert_eq!(
+        formatted,
+        vec![
+            "SELECT", "\ta,", "\tb", "FROM", "\tt", "WHERE", "\ta = 1;", ""
+        ]
+    );
+}
+
+#[test]
+fn test_format_str_lowercase() {
+    // The clauses and the keywords of the settings are matched whatever the case
+    let settings = Settings {
+        keywords_case: "lower".to_string(),
+        linebreak_before_keywords: ["and".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    assert_eq!(
+        format_str(&settings, "select a, b from t where x = 1 and y = 2;").unwrap(),
+        "select\n\ta,\n\tb\nfrom\n\tt\nwhere\n\tx = 1\n\tand y = 2;\n\n"
+    );
+}
+
+#[test]
+fn test_formatter_unsupported_case() {
+    let settings = Settings {
+        keywords_case: "camelcase".to_string(),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        Formatter::new(settings).unwrap_err(),
+        FormatError::UnsupportedCase { case, .. } if case == "camelcase"
+    ));
+}
+
+#[test]
+fn test_tokenizer_error_position() {
+    let error = format_str(
+        &Settings::default(),
+        "select a\nfrom t\nwhere b = 'unterminated",
+    )
+    .unwrap_err()
+    .with_path(Path::new("file.sql"));
+
+    assert_eq!(
+        error.span(),
+        Some(Span {
+            line: 3,
+            column: 11
+        })
+    );
+    assert!(
+        error
+            .to_string()
+            .starts_with("file.sql:3:11: Unterminated string literal")
+    );
+}
+
+#[test]
+fn test_ast_engine() {
+    let settings = Settings {
+        engine: "ast".to_string(),
+        tabulation_format: "space2".to_string(),
+        ..Default::default()
+    };
+    let formatted = format_str(
+        &settings,
+        "select a, count(*) from t where a in (select b from u) and c > 1 order by a;",
+    )
+    .unwrap();
+
+    assert_eq!(
+        formatted,
+        "SELECT\n  a,\n  COUNT(*)\nFROM\n  t\nWHERE\n  a IN (\n    SELECT\n      b\n    FROM\n      u\n  )\n  AND c > 1\nORDER BY\n  a;\n\n"
+    );
+
+    // No trailing whitespace is left by the pretty printer of the statements
+    let formatted = format_str(
+        &settings,
+        "create table t (name varchar(20), id int not null);",
+    )
+    .unwrap();
+    assert!(formatted.lines().all(|line| line.trim_end() == line));
+}
+
+#[test]
+fn test_ast_engine_fallback() {
+    let settings = Settings {
+        engine: "ast".to_string(),
+        ..Default::default()
+    };
+    let script = "select a -- comment\nfrom t;";
+    let formatter = Formatter::new(Settings::default()).unwrap();
+
+    assert_eq!(
+        format_str(&settings, script).unwrap(),
+        formatter.format(script).unwrap()
+    );
+
+    // The statements whose tokens are changed by the AST fall back on the token engine, even
+    // without the semantic check
+    let settings = Settings {
+        engine: "ast".to_string(),
+        semantic_check: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        format_str(&settings, "select a b from t;").unwrap(),
+        "SELECT\n\ta b\nFROM\n\tt;\n\n"
+    );
+}
+
+#[test]
+fn test_parenthesis_kinds() {
+    let settings = Settings {
+        linebreak_after_comma: false,
+        ..Default::default()
+    };
+    let formatted = format_str(
+        &settings,
+        "select count(*) from t where a in (1,2) and b in (select c from u);",
+    )
+    .unwrap();
+    let formatted = formatted.lines().map(str::trim_end).collect::<Vec<&str>>();
+
+    assert_eq!(
+        formatted,
+        vec![
+            "SELECT",
+            "\tCOUNT(*)",
+            "FROM",
+            "\tt",
+            "WHERE",
+            "\ta IN (1,2) AND b IN (",
+            "\t\tSELECT",
+            "\t\t\tc",
+            "\t\tFROM",
+            "\t\t\tu",
+            "\t);",
+            ""
+        ]
+    );
+
+    let formatted = format_str(&settings, "create table t (a varchar(20), b int);").unwrap();
+    assert_eq!(
+        formatted,
+        "CREATE TABLE t (\n\ta VARCHAR(20), b INT\n);\n\n"
+    );
+
+    // The rows of a VALUES clause are laid out as lists of values
+    let formatted = format_str(
+        &Settings::default(),
+        "insert into t (a,b) values (1, 2), (3, 4);",
+    )
+    .unwrap();
+    assert_eq!(
+        formatted,
+        "INSERT INTO t (\n\ta,\n\tb\n)\nVALUES\n\t(1, 2), (3, 4);\n\n"
+    );
+}
+
+#[test]
+fn test_comma_constructs() {
+    let formatted = format_str(
+        &Settings::default(),
+        "select a, round(b, 2) from t group by a, b;",
+    )
+    .unwrap();
+    let formatted = formatted.lines().map(str::trim_end).collect::<Vec<&str>>();
+
+    assert_eq!(
+        formatted,
+        vec![
+            "SELECT",
+            "\ta,",
+            "\tround(b, 2)",
+            "FROM",
+            "\tt",
+            "GROUP BY",
+            "\ta, b;",
+            ""
+        ]
+    );
+
+    let settings = Settings {
+        linebreak_after_comma_in: ["group_by".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    let formatted = format_str(&settings, "select a, b from t group by a, b;").unwrap();
+    assert!(formatted.starts_with("SELECT\n\ta, b"));
+    assert!(formatted.contains("GROUP BY\n\ta,\n"));
+
+    // The commas of the array and map literals don't belong to the clause
+    let settings = Settings {
+        linebreak_after_lbrace: false,
+        ..Default::default()
+    };
+    let formatted = format_str(&settings, "select array[1, 2], {'a': 1, 'b': 2} from t;").unwrap();
+    assert_eq!(
+        formatted,
+        "SELECT\n\tARRAY[1, 2],\n\t{'a': 1, 'b': 2}\nFROM\n\tt;\n\n"
+    );
+}
+
+#[test]
+fn test_clauses_indentation() {
+    let formatted = format_str(
+        &Settings::default(),
+        "select a from t left outer join u on t.id = u.id where a > 1 union all select b from v order by a limit 10;",
+    )
+    .unwrap();
+
+    assert_eq!(
+        formatted,
+        "SELECT\n\ta\nFROM\n\tt\nLEFT OUTER JOIN\n\tu ON t.id = u.id\nWHERE\n\ta > 1\nUNION ALL\nSELECT\n\tb\nFROM\n\tv\nORDER BY\n\ta\nLIMIT\n\t10;\n\n"
+    );
+
+    let settings = Settings {
+        clauses: ["SELECT", "FROM", "GROUP BY"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        linebreak_after_keywords: ["SELECT", "FROM", "GROUP BY"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let formatted = format_str(&settings, "select a from t where b = 1 group by a;").unwrap();
+    assert_eq!(
+        formatted,
+        "SELECT\n\ta\nFROM\n\tt WHERE b = 1\nGROUP BY\n\ta;\n\n"
+    );
+
+    // The clause keywords inside a function call aren't followed by a line break
+    let formatted = format_str(
+        &Settings::default(),
+        "select extract(year from d), string_agg(a, ',' order by b) over (partition by c order by d) from t;",
+    )
+    .unwrap();
+    assert_eq!(
+        formatted,
+        "SELECT\n\tEXTRACT(YEAR FROM d),\n\tstring_agg(a, ',' ORDER BY b) OVER (PARTITION BY c ORDER BY d)\nFROM\n\tt;\n\n"
+    );
+}
+
+#[test]
+fn test_linebreak_keyword_phrases() {
+    let settings = Settings {
+        indentation_clauses: false,
+        linebreak_after_keywords: ["SELECT", "FROM"].into_iter().map(String::from).collect(),
+        linebreak_before_keywords: ["LEFT OUTER JOIN", "UNION  ALL", "FROM"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        ..Default::default()
+    };
+    let formatted = format_str(
+        &settings,
+        "select a from t left   outer\njoin u on t.id = u.id union all select b from v;",
+    )
+    .unwrap();
+
+    assert_eq!(
+        formatted,
+        "SELECT\na\nFROM\nt\nLEFT OUTER JOIN u ON t.id = u.id\nUNION ALL SELECT\nb\nFROM\nv;\n\n"
+    );
+}
+
+#[test]
+fn test_comments_layout() {
+    let formatted = format_str(
+        &Settings::default(),
+        "-- header\nselect col1, -- customer id\ncol2 /* inline */ + 1\n-- before from\nfrom t -- the table\nwhere a = 1; -- done\n\n-- next\nselect 1;",
+    )
+    .unwrap();
+
+    assert_eq!(
+        formatted,
+        "-- header\nSELECT\n\tcol1, -- customer id\n\tcol2 /* inline */ + 1\n-- before from\nFROM\n\tt -- the table\nWHERE\n\ta = 1; -- done\n\n-- next\nSELECT\n\t1;\n\n"
+    );
+}
+
+#[test]
+fn test_directives() {
+    let script = "select a from t;\n-- sqlformater: off\ninsert into t values\n  (1,  'one'),\n  (10, 'ten');\n-- sqlformater: on\nselect b from u;\n-- sqlformater: skip-next-statement\nselect  c ,d   from v;\n";
+    let expected = "SELECT\n\ta\nFROM\n\tt;\n\n-- sqlformater: off\ninsert into t values\n  (1,  'one'),\n  (10, 'ten');\n-- sqlformater: on\nSELECT\n\tb\nFROM\n\tu;\n\n-- sqlformater: skip-next-statement\nselect  c ,d   from v;\n\n";
+
+    assert_eq!(format_str(&Settings::default(), script).unwrap(), expected);
+
+    let settings = Settings {
+        engine: "ast".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(format_str(&settings, script).unwrap(), expected);
+}
+
+#[test]
+fn test_semantic_check() {
+    let formatter = Formatter::new(Settings::default()).unwrap();
+    assert!(
+        formatter
+            .check_tokens("select a from t;", "SELECT\n\ta\nFROM\n\tt;")
+            .is_ok()
+    );
+
+    let error = formatter
+        .check_tokens("select a from t;", "SELECT\n\tb\nFROM\n\tt;")
+        .unwrap_err();
+    assert!(matches!(error, FormatError::SemanticChange { .. }));
+    assert_eq!(error.span(), Some(Span { line: 1, column: 8 }));
+
+    let error = formatter
+        .check_tokens("select a from t;", "SELECT\n\ta\nFROM\n\tt")
+        .unwrap_err();
+    assert_eq!(
+        error.span(),
+        Some(Span {
+            line: 1,
+            column: 16
+        })
+    );
+
+    // The AST engine adds the keyword AS to the alias, the token engine formats the statement
+    let settings = Settings {
+        engine: "ast".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        format_str(&settings, "select a b from t;").unwrap(),
+        "SELECT\n\ta b\nFROM\n\tt;\n\n"
+    );
+}
+
+#[test]
+fn test_quoted_tokens() {
+    // The quotes of the identifiers and the escaped quotes of the strings are kept
+    let script = "select \"Col\", [Col 2] from t where a = 'it''s' and b = \"a\"\"b\";";
+    for (settings, expected) in [
+        (
+            Settings::default(),
+            "SELECT\n\t\"Col\",\n\t[Col 2]\nFROM\n\tt\nWHERE\n\ta = 'it''s' AND b = \"a\"\"b\";\n\n",
+        ),
+        (
+            Settings {
+                database: "mssql".to_string(),
+                engine: "ast".to_string(),
+                ..Default::default()
+            },
+            "SELECT\n\t\"Col\",\n\t[Col 2]\nFROM\n\tt\nWHERE\n\ta = 'it''s'\n\tAND b = \"a\"\"b\";\n\n",
+        ),
+    ] {
+        assert_eq!(format_str(&settings, script).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_idempotence() {
+    let scripts = [
+        "select a,\n\n   b   ,\tc from t left outer join u on t.id = u.id where a in (select c from u where d = 1) and (b = 2 or c = 3) group by a order by a limit 10;",
+        "-- header\nselect col1, -- customer id\n  col2 /* inline */ + 1\nfrom t -- the table\nwhere a = 1\n  -- second condition\n  and b = 2; -- done\n",
+        "create table t (id int primary key, name varchar(20));\nselect f(g(1, 2)), arr[1], {'a': [1, 2]} from t;\nselect 1 ) from t;",
+    ];
+
+    // Each bit of the combination toggles one of the settings
+    for combination in (0u32..(1 << 13)).step_by(31) {
+        let bit = |i: u32| combination & (1 << i) != 0;
+        let layout = ParenthesisLayout {
+            linebreak: bit(9),
+            indentation: bit(10),
+        };
+        let settings = Settings {
+            linebreak_after_comma: bit(0),
+            linebreak_after_lparenthesis: bit(1),
+            linebreak_after_lbrace: bit(2),
+            linebreak_after_lbracket: bit(3),
+            linebreak_after_semicolon: bit(4),
+            indentation_parenthesis: bit(5),
+            indentation_braces: bit(6),
+            indentation_brackets: bit(7),
+            indentation_clauses: bit(8),
+            parenthesis_subquery: layout,
+            parenthesis_function_call: layout,
+            parenthesis_in_list: layout,
+            parenthesis_column_definitions: layout,
+            keywords_case: if bit(11) { "lowercase" } else { "uppercase" }.to_string(),
+            engine: if bit(12) { "ast" } else { "tokens" }.to_string(),
+            ..Default::default()
+        };
+        let formatter = Formatter::new(settings).unwrap();
+
+        for script in scripts {
+            if let Err(error) = formatter.check_idempotent(script) {
+                panic!("combination {:013b} : {}", combination, error);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_check_mode() {
+    let folder = TestFolder::new("check_mode");
+    let script = folder.join("script.sql");
+    fs::write(&script, "select a from t;").unwrap();
+    fs::create_dir_all(folder.join("settings")).unwrap();
+    fs::write(folder.join("settings/settings.json"), "{}").unwrap();
+
+    let args = |modes: &[&str]| {
+        [
+            format!("--logs_path={}", folder.join("logs").display()),
+            format!("--settings_path={}", folder.join("settings").display()),
+            script.display().to_string(),
+        ]
+        .into_iter()
+        .chain(modes.iter().map(|mode| mode.to_string()))
+        .collect::<Vec<String>>()
+    };
+
+    assert_eq!(cli::main(args(&["--check"])), cli::EXIT_CHANGED);
+    assert_eq!(fs::read_to_string(&script).unwrap(), "select a from t;");
+
+    // A missing path fails the run before any script is formatted
+    let missing = folder.join("missing.sql").display().to_string();
+    assert_eq!(cli::main(args(&[&missing])), cli::EXIT_ERROR);
+    assert_eq!(cli::main(args(&["--verbose"])), cli::EXIT_ERROR);
+    assert_eq!(fs::read_to_string(&script).unwrap(), "select a from t;");
+
+    assert_eq!(cli::main(args(&[])), cli::EXIT_SUCCESS);
+    assert_eq!(cli::main(args(&["--check"])), cli::EXIT_SUCCESS);
+
+    fs::write(&script, "select 'a from t;").unwrap();
+    assert_eq!(cli::main(args(&["--check"])), cli::EXIT_ERROR);
+}
+
+#[test]
+fn test_diff_mode() {
+    let folder = TestFolder::new("diff_mode");
+    folder.write("settings/settings.json", "{}");
+    folder.write(
+        "script.sql",
+        "SELECT\n\ta\nFROM\n\tt;\n\nselect b from u;\n",
+    );
+
+    let output = folder.sqlformater(&["script.sql", "--diff", "--settings_path=settings"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout,
+        "--- script.sql\n+++ script.sql\n@@ -3,4 +3,8 @@\n FROM\n \tt;\n \n-select b from u;\n+SELECT\n+\tb\n+FROM\n+\tu;\n+\n"
+    );
+    assert_eq!(
+        folder.read("script.sql"),
+        "SELECT\n\ta\nFROM\n\tt;\n\nselect b from u;\n"
+    );
+}
+
+#[test]
+fn test_stdin_mode() {
+    let folder = TestFolder::new("stdin_mode");
+    folder.write("settings.json", "{}");
+    let sqlformater = |args: &[&str], stdin: &str| {
+        folder.sqlformater_stdin(&[args, &["--settings_path=settings.json"]].concat(), stdin)
+    };
+
+    let output = sqlformater(&["-"], "select a from t;\n");
+    let formatted = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(formatted, "SELECT\n\ta\nFROM\n\tt;\n\n");
+
+    // The formatted script goes through unchanged
+    let output = sqlformater(&["--stdin"], &formatted);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), formatted);
+    assert_eq!(
+        sqlformater(&["-", "--check"], &formatted).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        sqlformater(&["-", "--check"], "select a from t;\n")
+            .status
+            .code(),
+        Some(1)
+    );
+
+    let output = sqlformater(&["-", "--stdin-filepath=query.sql"], "select 'a from t;\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("query.sql:1:8: Unterminated string literal")
+    );
+}
+
+#[test]
+fn test_lsp() {
+    use std::io::BufReader;
+
+    use lsp_server::{Message, Notification, Request, RequestId};
+
+    let folder = TestFolder::new("lsp");
+    folder.write(
+        "legacy/.sqlformater.json",
+        "{\"root\": true, \"keywords_case\": \"lower\"}",
+    );
+    let mut server = folder
+        .command(&["lsp"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+
+    let request = |id: i32, method: &str, params: serde_json::Value| {
+        Message::Request(Request::new(
+            RequestId::from(id),
+            method.to_string(),
+            params,
+        ))
+    };
+    let notification = |method: &str, params: serde_json::Value| {
+        Message::Notification(Notification::new(method.to_string(), params))
+    };
+    let mut send = |message: Message| message.write(&mut stdin).unwrap();
+    let mut receive = || Message::read(&mut stdout).unwrap().unwrap();
+    let uri = format!("file://{}/script.sql", folder.path().display());
+    let document = serde_json::json!({"uri": uri});
+
+    send(request(
+        1,
+        "initialize",
+        serde_json::json!({"capabilities": {}}),
+    ));
+    let Message::Response(response) = receive() else {
+        panic!("Expected the response to initialize");
+    };
+    assert!(response.result.unwrap()["capabilities"]["documentFormattingProvider"] == true);
+    send(notification("initialized", serde_json::json!({})));
+
+    send(notification(
+        "textDocument/didOpen",
+        serde_json::json!({"textDocument": {
+            "uri": uri, "languageId": "sql", "version": 1, "text": "select 'a"
+        }}),
+    ));
+    let Message::Notification(diagnostics) = receive() else {
+        panic!("Expected the diagnostics");
+    };
+    assert_eq!(diagnostics.method, "textDocument/publishDiagnostics");
+    assert_eq!(
+        diagnostics.params["diagnostics"][0]["range"]["start"],
+        serde_json::json!({"line": 0, "character": 7})
+    );
+
+    send(notification(
+        "textDocument/didChange",
+        serde_json::json!({
+            "textDocument": {"uri": uri, "version": 2},
+            "contentChanges": [{"text": "select a\nfrom t;"}]
+        }),
+    ));
+    let Message::Notification(diagnostics) = receive() else {
+        panic!("Expected the diagnostics");
+    };
+    assert_eq!(diagnostics.params["diagnostics"], serde_json::json!([]));
+
+    send(request(
+        2,
+        "textDocument/formatting",
+        serde_json::json!({
+            "textDocument": document,
+            "options": {"tabSize": 4, "insertSpaces": false}
+        }),
+    ));
+    let Message::Response(response) = receive() else {
+        panic!("Expected the response to formatting");
+    };
+    assert_eq!(
+        response.result.unwrap(),
+        serde_json::json!([{
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 7}},
+            "newText": "SELECT\n\ta\nFROM\n\tt;\n\n"
+        }])
+    );
+
+    send(request(
+        3,
+        "textDocument/onTypeFormatting",
+        serde_json::json!({
+            "textDocument": document,
+            "position": {"line": 1, "character": 7},
+            "ch": ";",
+            "options": {"tabSize": 4, "insertSpaces": false}
+        }),
+    ));
+    let Message::Response(response) = receive() else {
+        panic!("Expected the response to onTypeFormatting");
+    };
+    assert_eq!(
+        response.result.unwrap(),
+        serde_json::json!([{
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 7}},
+            "newText": "SELECT\n\ta\nFROM\n\tt;"
+        }])
+    );
+
+    // The settings are resolved from the path of each document
+    let uri = format!("file://{}/legacy/script.sql", folder.path().display());
+    send(notification(
+        "textDocument/didOpen",
+        serde_json::json!({"textDocument": {
+            "uri": uri, "languageId": "sql", "version": 1, "text": "SELECT a FROM t;"
+        }}),
+    ));
+    receive();
+    send(request(
+        4,
+        "textDocument/formatting",
+        serde_json::json!({
+            "textDocument": {"uri": uri},
+            "options": {"tabSize": 4, "insertSpaces": false}
+        }),
+    ));
+    let Message::Response(response) = receive() else {
+        panic!("Expected the response to formatting");
+    };
+    assert_eq!(
+        response.result.unwrap()[0]["newText"],
+        "select\n\ta\nfrom\n\tt;\n\n"
+    );
+
+    send(request(5, "shutdown", serde_json::Value::Null));
+    receive();
+    send(notification("exit", serde_json::Value::Null));
+    assert!(server.wait().unwrap().success());
+}
+
+#[test]
+fn test_range_formatting() {
+    let formatter = Formatter::new(Settings::default()).unwrap();
+    let script = "select  a from t;\nbegin;\n    select b,\n    c from u where s = 'x\n  y';\n    select  d from v;\ncommit;\n";
+
+    assert_eq!(
+        formatter.format_lines(script, 3, 3).unwrap(),
+        "select  a from t;\nbegin;\n    SELECT\n    \tb,\n    \tc\n    FROM\n    \tu\n    WHERE\n    \ts = 'x\n  y';\n    select  d from v;\ncommit;\n"
+    );
+    assert_eq!(
+        formatter.format_lines(script, 1, 2).unwrap(),
+        "SELECT\n\ta\nFROM\n\tt;\n\nBEGIN;\n    select b,\n    c from u where s = 'x\n  y';\n    select  d from v;\ncommit;\n"
+    );
+
+    let offset = script.find("d from").unwrap();
+    assert_eq!(
+        formatter.format_range(script, offset..offset).unwrap(),
+        script.replace("select  d from v;", "SELECT\n    \td\n    FROM\n    \tv;")
+    );
+
+    // The statements protected by a directive are left untouched
+    let script = "-- sqlformater: off\nselect   a,b   from t;\n-- sqlformater: on\nselect  c from u;\n-- sqlformater: skip-next-statement\nselect   d from v;\n";
+    assert_eq!(formatter.format_lines(script, 2, 2).unwrap(), script);
+    assert_eq!(formatter.format_lines(script, 6, 6).unwrap(), script);
+    assert_eq!(
+        formatter.format_lines(script, 2, 6).unwrap(),
+        script.replace("select  c from u;", "SELECT\n\tc\nFROM\n\tu;")
+    );
+}
+
+#[test]
+fn test_git_changes() {
+    let folder = TestFolder::new("git_changes");
+    folder.write(".settings/settings.json", "{}");
+    folder.git(&["init", "-q"]);
+    folder.git(&["config", "user.email", "tests@sqlformater"]);
+    folder.git(&["config", "user.name", "tests"]);
+    folder.write("a.sql", "select a from t;\n");
+    folder.write("b.sql", "select b from t;\n");
+    folder.git(&["add", "."]);
+    folder.git(&["commit", "-qm", "init"]);
+    folder.write("b.sql", "select b from t;\nselect c from u;\n");
+    // The untracked scripts are changes since any revision, unless they are ignored
+    folder.write(".gitignore", "d.sql\n");
+    folder.write("c.sql", "select c from t;\n");
+    folder.write("d.sql", "select d from t;\n");
+    // The revision isn't taken as a path
+    folder.write("HEAD", "");
+
+    let settings_path = format!("--settings_path={}", folder.join(".settings").display());
+    let sqlformater =
+        |args: &[&str]| folder.sqlformater(&[args, &[settings_path.as_str()]].concat());
+
+    let output = sqlformater(&["--check", "--changed-since=HEAD"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("b.sql") && !stdout.contains("a.sql"));
+    assert!(stdout.contains("c.sql") && !stdout.contains("d.sql"));
+
+    let output = sqlformater(&["--diff", "--changed-since=HEAD", "--changed-lines"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" select b from t;\n-select c from u;\n+SELECT\n+\tc\n+FROM\n+\tu;\n"));
+    assert!(stdout.contains("-select c from t;\n+SELECT\n+\tc\n+FROM\n+\tt;\n"));
+
+    folder.git(&["add", "b.sql"]);
+    assert_eq!(sqlformater(&["--staged"]).status.code(), Some(0));
+    assert_eq!(
+        folder.read("b.sql"),
+        "SELECT\n\tb\nFROM\n\tt;\n\nSELECT\n\tc\nFROM\n\tu;\n\n"
+    );
+    assert_eq!(folder.read("a.sql"), "select a from t;\n");
+    assert_eq!(folder.read("c.sql"), "select c from t;\n");
+
+    // The lines changed in the index don't match the working copy formatted above
+    let output = sqlformater(&["--staged", "--changed-lines"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unstaged changes"));
+    folder.git(&["add", "b.sql"]);
+    assert_eq!(
+        sqlformater(&["--staged", "--changed-lines"]).status.code(),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_pre_commit_hook() {
+    let folder = TestFolder::new("pre_commit_hook");
+    folder.git(&["init", "-q"]);
+    folder.git(&["config", "user.email", "tests@sqlformater"]);
+    folder.git(&["config", "user.name", "tests"]);
+
+    // The existing hook is kept and run before the formatter
+    let hook = folder.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\ntouch previous_hook\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    assert_eq!(folder.sqlformater(&["install-hook"]).status.code(), Some(0));
+    assert!(
+        folder
+            .join(".git/hooks/pre-commit.before-sqlformater")
+            .exists()
+    );
+
+    folder.write("a.sql", "select a from t;\n");
+    folder.git(&["add", "a.sql"]);
+    assert!(!folder.git(&["commit", "-qm", "check"]).status.success());
+    assert!(folder.join("previous_hook").exists());
+
+    // The content staged is checked, not the working copy
+    folder.write("a.sql", "SELECT\n\ta\nFROM\n\tt;\n\n");
+    assert!(!folder.git(&["commit", "-qm", "check"]).status.success());
+
+    // The scripts partially staged are refused in fix mode
+    assert_eq!(
+        folder.sqlformater(&["install-hook", "--fix"]).status.code(),
+        Some(0)
+    );
+    let output = folder.git(&["commit", "-qm", "fix"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stage or stash"));
+
+    folder.write("a.sql", "select a from t;\n");
+    assert!(folder.git(&["commit", "-qm", "fix"]).status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&folder.git(&["show", "HEAD:a.sql"]).stdout),
+        "SELECT\n\ta\nFROM\n\tt;\n\n"
+    );
+
+    assert_eq!(folder.sqlformater(&["install-hook"]).status.code(), Some(0));
+    folder.write("b.sql", "SELECT\n\tb\nFROM\n\tt;\n\n");
+    folder.git(&["add", "b.sql"]);
+    folder.write("b.sql", "select b from t;\n");
+    assert!(folder.git(&["commit", "-qm", "check"]).status.success());
+
+    assert_eq!(
+        folder.sqlformater(&["uninstall-hook"]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        fs::read_to_string(&hook).unwrap(),
+        "#!/bin/sh\ntouch previous_hook\n"
+    );
+    assert_eq!(
+        folder.sqlformater(&["uninstall-hook"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn test_watch_mode() {
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let folder = TestFolder::new("watch_mode");
+    folder.write("scripts/a.sql", "select a from t;\n");
+    folder.write("scripts/.sqlformaterignore", "generated/\n");
+    fs::create_dir_all(folder.join("scripts/generated")).unwrap();
+    fs::create_dir_all(folder.join("scripts/vendor")).unwrap();
+    folder.write(".settings/settings.json", "{}");
+
+    let mut child = folder
+        .command(&["scripts", "--watch", "--exclude=vendor"])
+        .arg(format!(
+            "--settings_path={}",
+            folder.join(".settings").display()
+        ))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    lines
+        .by_ref()
+        .map_while(Result::ok)
+        .find(|line| line.starts_with("Watching"));
+
+    // The existing scripts are formatted before watching
+    assert_eq!(folder.read("scripts/a.sql"), "SELECT\n\ta\nFROM\n\tt;\n\n");
+
+    // The ignored scripts are written before, so they are formatted first if they are watched
+    folder.write("scripts/generated/c.sql", "select c from t;\n");
+    folder.write("scripts/vendor/d.sql", "select d from t;\n");
+    folder.write("scripts/b.sql", "select b from t;\n");
+    let start = Instant::now();
+    let mut script = String::new();
+    while start.elapsed() < Duration::from_secs(10) {
+        script = fs::read_to_string(folder.join("scripts/b.sql")).unwrap_or_default();
+        if script != "select b from t;\n" {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    assert_eq!(script, "SELECT\n\tb\nFROM\n\tt;\n\n");
+    assert_eq!(folder.read("scripts/generated/c.sql"), "select c from t;\n");
+    assert_eq!(folder.read("scripts/vendor/d.sql"), "select d from t;\n");
+}
+
+#[test]
+fn test_scripts_selection() {
+    let folder = TestFolder::new("scripts_selection");
+    for path in [
+        "queries/a.sql",
+        "queries/b.gen.sql",
+        "queries/nested/c.sql",
+        "vendor/d.sql",
+        "target/e.sql",
+        "generated/f.sql",
+    ] {
+        folder.write(path, "select a from t;\n");
+    }
+    folder.git(&["init", "-q"]);
+    folder.write(".gitignore", "target/\n");
+    folder.write(".sqlformaterignore", "generated/\n");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(folder.path(), folder.join("queries/nested/loop")).unwrap();
+    folder.write(".settings/settings.json", "{}");
+
+    let checked = |args: &[&str]| {
+        let output = folder
+            .command(&[&[".", "--check"], args].concat())
+            .arg(format!(
+                "--settings_path={}",
+                folder.join(".settings").display()
+            ))
+            .output()
+            .unwrap();
+        let mut scripts = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.strip_prefix("Would reformat : "))
+            .map(|path| {
+                Path::new(path)
+                    .strip_prefix(folder.path())
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect::<Vec<String>>();
+        scripts.sort();
+        scripts
+    };
+
+    assert_eq!(
+        checked(&[]),
+        [
+            "queries/a.sql",
+            "queries/b.gen.sql",
+            "queries/nested/c.sql",
+            "vendor/d.sql"
+        ]
+    );
+    assert_eq!(
+        checked(&["--exclude=vendor", "--exclude=*.gen.sql"]),
+        ["queries/a.sql", "queries/nested/c.sql"]
+    );
+    assert_eq!(
+        checked(&["--include=queries/**", "--exclude=**/nested"]),
+        ["queries/a.sql", "queries/b.gen.sql"]
+    );
+}
+
+#[test]
+fn test_settings_overrides() {
+    let folder = TestFolder::new("settings_overrides");
+    let settings = serde_json::json!({
+        "extensions": ["sql", ".pgsql", "tsql"],
+        "overrides": [
+            {"files": ["*.pgsql"], "settings": {"database": "postgre", "keywords_case": "lower"}},
+            {"files": ["queries/legacy/**"], "settings": {"tabulation_format": "space2"}}
+        ]
+    });
+    folder.write("settings.json", &settings.to_string());
+    for path in ["a.sql", "b.pgsql", "c.tsql", "d.txt"] {
+        folder.write(&format!("queries/{}", path), "select a from t;\n");
+    }
+
+    let settings_path = format!("--settings_path={}", folder.join("settings.json").display());
+    let sqlformater = |args: &[&str], stdin: Option<&str>| {
+        let args = [args, &[settings_path.as_str()]].concat();
+        match stdin {
+            Some(stdin) => folder.sqlformater_stdin(&args, stdin),
+            None => folder.sqlformater(&args),
+        }
+    };
+
+    assert_eq!(sqlformater(&["queries"], None).status.code(), Some(0));
+    assert_eq!(folder.read("queries/a.sql"), "SELECT\n\ta\nFROM\n\tt;\n\n");
+    assert_eq!(
+        folder.read("queries/b.pgsql"),
+        "select\n\ta\nfrom\n\tt;\n\n"
+    );
+    assert_eq!(folder.read("queries/c.tsql"), "SELECT\n\ta\nFROM\n\tt;\n\n");
+    assert_eq!(folder.read("queries/d.txt"), "select a from t;\n");
+
+    // The settings of the script read from stdin are resolved from its path
+    let output = sqlformater(
+        &["-", "--stdin-filepath=queries/legacy/e.pgsql"],
+        Some("select a from t;\n"),
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "select\n  a\nfrom\n  t;\n\n"
+    );
+
+    let settings = serde_json::json!({
+        "overrides": [{"files": ["*.sql"], "settings": {"engine": "unknown"}}]
+    });
+    folder.write("settings.json", &settings.to_string());
+    assert_eq!(sqlformater(&["queries"], None).status.code(), Some(2));
+}
+
+#[test]
+fn test_settings_discovery() {
+    let folder = TestFolder::new("settings_discovery");
+    // The settings don't depend on the current directory
+    let elsewhere = TestFolder::new("settings_discovery_elsewhere");
+    // Not read as the configuration file of the project is the root
+    folder.write(".sqlformater.json", "{ invalid");
+    let settings = serde_json::json!({
+        "root": true,
+        "keywords_case": "lower",
+        "overrides": [{"files": ["legacy/**"], "settings": {"tabulation_format": "space2"}}]
+    });
+    folder.write("project/.sqlformater.json", &settings.to_string());
+    folder.write(
+        "project/sub/sqlformater.toml",
+        "tabulation_format = \"space4\"\n",
+    );
+    for path in ["project/a.sql", "project/legacy/b.sql", "project/sub/c.sql"] {
+        folder.write(path, "select a from t;\n");
+    }
+
+    let project = folder.join("project").display().to_string();
+    assert_eq!(elsewhere.sqlformater(&[&project]).status.code(), Some(0));
+    assert_eq!(folder.read("project/a.sql"), "select\n\ta\nfrom\n\tt;\n\n");
+    assert_eq!(
+        folder.read("project/legacy/b.sql"),
+        "select\n  a\nfrom\n  t;\n\n"
+    );
+    assert_eq!(
+        folder.read("project/sub/c.sql"),
+        "select\n    a\nfrom\n    t;\n\n"
+    );
+
+    // The extensions of the scripts are the ones of the project, not of the current directory
+    folder.write(
+        "pgsql/sqlformater.toml",
+        "root = true\nextensions = [\"pgsql\"]\n",
+    );
+    folder.write("pgsql/d.pgsql", "select d from t;\n");
+    folder.write("pgsql/e.sql", "select e from t;\n");
+    let pgsql = folder.join("pgsql").display().to_string();
+    let output = elsewhere.sqlformater(&[&pgsql, "--check"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("d.pgsql") && !stdout.contains("e.sql"));
+    let script = folder.join("pgsql/d.pgsql").display().to_string();
+    assert_eq!(
+        elsewhere.sqlformater(&[&script, "--check"]).status.code(),
+        Some(1)
+    );
+
+    folder.write("project/sub/sqlformater.toml", "tabulation_format = 4\n");
+    let output = elsewhere.sqlformater(&[&project]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sqlformater.toml:1:21: "));
+}
+
+#[test]
+fn test_settings_formats() {
+    let folder = TestFolder::new("settings_formats");
+    folder.write(
+        "python/pyproject.toml",
+        "[project]\nname = \"queries\"\n\n[tool.sqlformater]\nroot = true\nkeywords_case = \"lower\"\n",
+    );
+    folder.write(
+        "yaml/.sqlformater.yaml",
+        "root: true\ntabulation_format: space2\nlinebreak_after_comma_in:\n  - select\n",
+    );
+    for path in ["python/a.sql", "yaml/b.sql", "c.sql"] {
+        folder.write(path, "select a, b from t;\n");
+    }
+
+    assert_eq!(
+        folder.sqlformater(&["python", "yaml"]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        folder.read("python/a.sql"),
+        "select\n\ta,\n\tb\nfrom\n\tt;\n\n"
+    );
+    assert_eq!(
+        folder.read("yaml/b.sql"),
+        "SELECT\n  a,\n  b\nFROM\n  t;\n\n"
+    );
+
+    // The settings path could be any of these formats
+    folder.write("config/settings.toml", "tabulation_format = \"space4\"\n");
+    let settings_path = format!(
+        "--settings_path={}",
+        folder.join("config/settings.toml").display()
+    );
+    assert_eq!(
+        folder.sqlformater(&["c.sql", &settings_path]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        folder.read("c.sql"),
+        "SELECT\n    a,\n    b\nFROM\n    t;\n\n"
+    );
+
+    folder.write(
+        "yaml/.sqlformater.yaml",
+        "root: true\nindentation_clauses: yes please\n",
+    );
+    let output = folder.sqlformater(&["yaml"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains(".sqlformater.yaml:2:22: indentation_clauses: invalid type")
+    );
+}
+
+#[test]
+fn test_partial_parenthesis_layout() {
+    let settings: Settings = serde_json::from_value(serde_json::json!({
+        "parenthesis_subquery": {"linebreak": false},
+        "parenthesis_in_list": {"indentation": true}
+    }))
+    .unwrap();
+    // The absent fields keep the default of their kind
+    assert_eq!(
+        settings.parenthesis_subquery,
+        ParenthesisLayout {
+            linebreak: false,
+            indentation: true
+        }
+    );
+    assert_eq!(
+        settings.parenthesis_in_list,
+        ParenthesisLayout {
+            linebreak: false,
+            indentation: true
+        }
+    );
+
+    let folder = TestFolder::new("partial_parenthesis_layout");
+    let script = "select a from (select b from u) v;\n";
+    folder.write(
+        "sqlformater.toml",
+        "root = true\n\n[parenthesis_subquery]\nlinebreak = false\n\n[[overrides]]\nfiles = [\"legacy/**\"]\nsettings = { parenthesis_subquery = { indentation = false } }\n",
+    );
+    folder.write("a.sql", script);
+    folder.write("legacy/b.sql", script);
+
+    assert_eq!(folder.sqlformater(&["."]).status.code(), Some(0));
+    let expected = |indentation| {
+        let settings = Settings {
+            parenthesis_subquery: ParenthesisLayout {
+                linebreak: false,
+                indentation,
+            },
+            ..Default::default()
+        };
+        format_str(&settings, script).unwrap()
+    };
+    assert_eq!(folder.read("a.sql"), expected(true));
+    // The partial layout of an override is merged with the layout of the file
+    assert_eq!(folder.read("legacy/b.sql"), expected(false));
+    assert_ne!(expected(true), expected(false));
+}
+
+#[test]
+fn test_settings_init() {
+    let folder = TestFolder::new("settings_init");
+    folder.write("a.sql", "select a, b from t;\n");
+
+    // A run writes no settings file, only its logs : without --logs_path, in ./sqlformater
+    let output = Command::new(env!("CARGO_BIN_EXE_sqlformater"))
+        .arg("a.sql")
+        .current_dir(folder.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let mut written = fs::read_dir(folder.join("sqlformater"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    written.sort();
+    assert_eq!(written, [".gitignore", "logs.txt"]);
+    assert!(!folder.join("sqlformater.toml").exists());
+    let formatted = folder.read("a.sql");
+
+    // A settings file is never rewritten, even with the clauses implied by indentation_clauses
+    let settings = "{\"keywords_case\": \"lower\", \"linebreak_after_keywords\": []}";
+    folder.write("legacy/settings.json", settings);
+    assert_eq!(
+        folder
+            .sqlformater(&["a.sql", "--check", "--settings_path=legacy"])
+            .status
+            .code(),
+        Some(1)
+    );
+    assert_eq!(folder.read("legacy/settings.json"), settings);
+
+    // The templates hold the default settings
+    let output = folder.sqlformater(&["init"]);
+    assert_eq!(output.status.code(), Some(0));
+    let template = folder.read("sqlformater.toml");
+    assert!(template.starts_with("# Settings of sqlformater"));
+    assert!(template.contains("\n# Case of the keywords"));
+    assert_eq!(
+        folder.sqlformater(&["a.sql", "--check"]).status.code(),
+        Some(0)
+    );
+    assert_eq!(folder.read("a.sql"), formatted);
+
+    for path in ["config.yaml", "settings.json"] {
+        let settings_path = format!("--settings_path={}", path);
+        assert_eq!(
+            folder.sqlformater(&["init", &settings_path]).status.code(),
+            Some(0)
+        );
+        assert_eq!(
+            folder
+                .sqlformater(&["a.sql", "--check", &settings_path])
+                .status
+                .code(),
+            Some(0)
+        );
+    }
+
+    // An existing settings file isn't replaced
+    folder.write("sqlformater.toml", "root = true\n");
+    let output = folder.sqlformater(&["init"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert_eq!(folder.read("sqlformater.toml"), "root = true\n");
+}
+
+#[test]
+fn test_settings_validation() {
+    let folder = TestFolder::new("settings_validation");
+    folder.write("a.sql", "select a from t;\n");
+
+    let sqlformater = |settings: &str, content: &str| {
+        folder.write(settings, content);
+        let output = folder.sqlformater(&["a.sql", &format!("--settings_path={}", settings)]);
+        assert_eq!(output.status.code(), Some(2));
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+
+    assert!(
+        sqlformater("settings.json", "{\n  \"keyword_case\": \"lower\"\n}").contains(
+            "settings.json:2:16: unknown field `keyword_case`, did you mean `keywords_case`?"
+        )
+    );
+    assert!(
+        sqlformater("settings.toml", "tabulation_format = \"spaces4\"\n").contains(
+            "settings.toml:1:21: unsupported tabulation_format `spaces4`, did you mean `space4`?"
+        )
+    );
+    assert!(
+        sqlformater("settings.json", "{\"database\": \"oracle\"}")
+            .contains("unsupported database `oracle`, expected one of `ansi`")
+    );
+    assert!(
+        sqlformater(
+            "settings.json",
+            "{\"parenthesis_subquery\": {\"linebrake\": true}}"
+        )
+        .contains("unknown field `linebrake`, did you mean `linebreak`?")
+    );
+    assert!(
+        sqlformater(
+            "settings.yaml",
+            "overrides:\n  - files: [\"*.sql\"]\n    settings: {engine: tokenz}\n"
+        )
+        .contains("settings.yaml:3:15: overrides[0].settings: unsupported engine `tokenz`, did you mean `tokens`?")
+    );
+    assert!(
+        sqlformater(
+            "settings.toml",
+            "[[overrides]]\nfiles = [\"*.sql\"]\nsettings = { keyword_case = \"lower\" }\n"
+        )
+        .contains(
+            "settings.toml:3:14: unknown field `keyword_case`, did you mean `keywords_case`?"
+        )
+    );
+    assert_eq!(folder.read("a.sql"), "select a from t;\n");
+
+    // The setting root is a valid field
+    folder.write(
+        ".sqlformater.json",
+        "{\"root\": true, \"keywords_case\": \"lower\"}",
+    );
+    assert_eq!(folder.sqlformater(&["a.sql"]).status.code(), Some(0));
+    assert_eq!(folder.read("a.sql"), "select\n\ta\nfrom\n\tt;\n\n");
+}
+
+#[test]
+fn test_formatter_invalid_tabulation_format() {
+    let settings = Settings {
+        tabulation_format: "tabs".to_string(),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        Formatter::new(settings),
+        Err(FormatError::InvalidSettings { message, .. }) if message.ends_with("did you mean `tab1`?")
+    ));
+}